@@ -0,0 +1,276 @@
+use std::{path::PathBuf, time::Duration};
+
+use scram_capture::{CaptureMode, DeviceSelector, Pacing, PcmFormat, Voice};
+use scram_process::{
+    Processor,
+    config::{Analysis, ChannelMap, FrequencyScale, Hop, Tilt, Weighting},
+};
+
+const USAGE: &str = "\
+usage: scram [options]
+
+options:
+    -l, --list-devices      list the available capture devices and exit
+    -d, --device <device>   capture from this device, by index or name
+                            (exact, or a unique case-insensitive substring)
+    -i, --input             capture from an input device (microphone, line-in)
+                            instead of the loopback of an output device
+    -c, --channels <l,r>    use these (zero-based) channels as left and right
+                            instead of downmixing every channel
+    -o, --overlap <percent> how much consecutive analysis windows overlap, 50 by default
+        --fft-size <n>      frames per channel in each analysis window, 2048 by
+                            default. Powers of two are fastest
+    -s, --scale <scale>     how bands are spaced: mel (default), bark, erb, linear,
+                            log, octave[:<n>] for 1/n-octave bands, or
+                            edges:<hz>,<hz>,.. for bands between the given edges
+        --cqt <n>[:<hz>]    use a constant-Q transform with n bins per octave, from
+                            <hz> (32.7 by default) up, instead of an fft
+        --fixed-bands       use the bands the scale has, rather than one per column.
+                            Only octave scales have a set of their own
+        --tilt <db>[:<hz>]  tilt the spectrum by this many dB per octave around <hz>
+                            (1000 by default). -3 levels out pink noise on scales with
+                            rectangular bands
+        --weighting <curve> weight frequencies by how loud they sound: none (default),
+                            a, c, k (as loudness meters use) or 468 (ITU-R 468)
+        --rate <hz>         resample to this rate before analysis, so bands look the
+                            same whatever rate the device runs at
+    -f, --file <path>       play back a wav file instead of capturing
+        --loop              start the file over when it ends
+    -g, --generator <signal>
+                            generate a test signal instead of capturing, given once
+                            per channel. <signal>[@<amplitude>] is one of:
+                                silence, white, pink, sine:<hz>, square:<hz>,
+                                saw:<hz>, impulse:<seconds>, chord:<hz>,<hz>,..
+                                sweep:<hz>:<hz>:<seconds>[:lin|:log]
+        --unpaced           read the file or generator as fast as possible,
+                            rather than in real-time
+    -r, --raw <source>      read raw interleaved pcm instead of capturing, from
+                            stdin (-), a file descriptor (fd:<n>), a file or a fifo
+        --pcm-format <fmt>  raw sample format: s16le (default), s32le or f32le
+        --pcm-rate <hz>     raw sample rate, 48000 by default
+        --pcm-channels <n>  raw channel count, 2 by default
+        --stats             show capture statistics over the visualization
+        --record <path>     record what is being visualized to a wav file
+        --record-last <seconds>
+                            only keep the last few seconds of the recording, and
+                            write them to the --record path on exit, or whenever
+                            the process gets SIGUSR1
+    -h, --help              print this message and exit
+";
+
+/// Where raw pcm is read from
+pub enum RawSource {
+    Stdin,
+    Fd(i32),
+    Path(PathBuf),
+}
+
+impl std::str::FromStr for RawSource {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Ok(match input {
+            "-" => Self::Stdin,
+            input => match input.strip_prefix("fd:") {
+                Some(fd) => Self::Fd(fd.parse()?),
+                None => Self::Path(input.into()),
+            },
+        })
+    }
+}
+
+pub struct RawOptions {
+    pub source: RawSource,
+    pub format: PcmFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+#[derive(Default)]
+pub struct Args {
+    pub list_devices: bool,
+    pub device: DeviceSelector,
+    pub mode: CaptureMode,
+    pub channel_map: ChannelMap,
+    pub hop: Hop,
+    pub fft_size: Option<usize>,
+    pub scale: FrequencyScale,
+    pub analysis: Analysis,
+    pub fixed_bands: bool,
+    pub tilt: Tilt,
+    pub weighting: Weighting,
+    pub rate: Option<u32>,
+    pub file: Option<PathBuf>,
+    pub looping: bool,
+    pub voices: Vec<Voice>,
+    pub pacing: Pacing,
+    pub raw: Option<RawOptions>,
+    pub stats: bool,
+    pub record: Option<PathBuf>,
+    pub record_last: Option<Duration>,
+}
+
+impl Args {
+    pub fn parse() -> anyhow::Result<Self> {
+        let mut this = Self::default();
+
+        let mut raw_source = None;
+        let mut raw_format = PcmFormat::default();
+        let mut raw_rate = 48_000;
+        let mut raw_channels = 2;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| anyhow::anyhow!("{name} requires a value"))
+            };
+
+            match arg.as_str() {
+                "-l" | "--list-devices" => this.list_devices = true,
+                "-d" | "--device" => this.device = value("--device")?.parse()?,
+                "-i" | "--input" => this.mode = CaptureMode::Input,
+                "-c" | "--channels" => this.channel_map = parse_pair(&value("--channels")?)?,
+                "-o" | "--overlap" => {
+                    let percent: f32 = value("--overlap")?.parse()?;
+                    anyhow::ensure!((0.0..100.0).contains(&percent), "overlap must be in 0..100");
+                    this.hop = Hop::Overlap(percent / 100.0)
+                }
+                "--fft-size" => {
+                    let size: usize = value("--fft-size")?.parse()?;
+                    anyhow::ensure!(
+                        (Processor::MIN_SAMPLE_SIZE..=Processor::MAX_SAMPLE_SIZE).contains(&size),
+                        "--fft-size must be in {}..={}",
+                        Processor::MIN_SAMPLE_SIZE,
+                        Processor::MAX_SAMPLE_SIZE
+                    );
+                    this.fft_size = Some(size)
+                }
+                "-s" | "--scale" => this.scale = parse_scale(&value("--scale")?)?,
+                "--cqt" => this.analysis = parse_cqt(&value("--cqt")?)?,
+                "--fixed-bands" => this.fixed_bands = true,
+                "--tilt" => this.tilt = parse_tilt(&value("--tilt")?)?,
+                "--weighting" => this.weighting = parse_weighting(&value("--weighting")?)?,
+                "--rate" => {
+                    let rate: u32 = value("--rate")?.parse()?;
+                    anyhow::ensure!(rate > 0, "--rate must be positive");
+                    this.rate = Some(rate)
+                }
+                "-f" | "--file" => this.file = Some(value("--file")?.into()),
+                "--loop" => this.looping = true,
+                "-g" | "--generator" => this.voices.push(value("--generator")?.parse()?),
+                "--unpaced" => this.pacing = Pacing::Unpaced,
+                "-r" | "--raw" => raw_source = Some(value("--raw")?.parse()?),
+                "--pcm-format" => raw_format = value("--pcm-format")?.parse()?,
+                "--pcm-rate" => raw_rate = value("--pcm-rate")?.parse()?,
+                "--pcm-channels" => raw_channels = value("--pcm-channels")?.parse()?,
+                "--stats" => this.stats = true,
+                "--record" => this.record = Some(value("--record")?.into()),
+                "--record-last" => {
+                    let secs: f64 = value("--record-last")?.parse()?;
+                    anyhow::ensure!(secs > 0.0, "--record-last must be positive");
+                    this.record_last = Some(Duration::from_secs_f64(secs))
+                }
+                "-h" | "--help" => {
+                    print!("{USAGE}");
+                    std::process::exit(0)
+                }
+                arg => anyhow::bail!("unknown argument: {arg}\n\n{USAGE}"),
+            }
+        }
+
+        this.raw = raw_source.map(|source| RawOptions {
+            source,
+            format: raw_format,
+            sample_rate: raw_rate,
+            channels: raw_channels,
+        });
+
+        anyhow::ensure!(
+            this.record_last.is_none() || this.record.is_some(),
+            "--record-last needs a --record path to write to"
+        );
+
+        Ok(this)
+    }
+}
+
+fn parse_pair(input: &str) -> anyhow::Result<ChannelMap> {
+    let (left, right) = input
+        .split_once(',')
+        .ok_or_else(|| anyhow::anyhow!("expected a left,right pair, got {input:?}"))?;
+
+    Ok(ChannelMap::Pair {
+        left: left.trim().parse()?,
+        right: right.trim().parse()?,
+    })
+}
+
+fn parse_scale(input: &str) -> anyhow::Result<FrequencyScale> {
+    let (name, fraction) = match input.split_once(':') {
+        Some((name, fraction)) => (name, Some(fraction)),
+        None => (input, None),
+    };
+
+    Ok(match (name, fraction) {
+        ("mel", None) => FrequencyScale::Mel,
+        ("bark", None) => FrequencyScale::Bark,
+        ("erb", None) => FrequencyScale::Erb,
+        ("linear", None) => FrequencyScale::Linear,
+        ("log", None) => FrequencyScale::Logarithmic,
+        ("octave", fraction) => {
+            let fraction = fraction.map_or(Ok(1), str::parse)?;
+            anyhow::ensure!(fraction > 0, "octave fractions must be positive");
+            FrequencyScale::Octave { fraction }
+        }
+        ("edges", Some(edges)) => FrequencyScale::Custom(
+            edges
+                .split(',')
+                .map(|edge| edge.trim().parse())
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => anyhow::bail!("unknown scale: {input}"),
+    })
+}
+
+fn parse_cqt(input: &str) -> anyhow::Result<Analysis> {
+    // C1
+    const MIN_FREQUENCY: f32 = 32.703;
+
+    let (bins, min) = match input.split_once(':') {
+        Some((bins, min)) => (bins, min.parse()?),
+        None => (input, MIN_FREQUENCY),
+    };
+
+    let bins_per_octave = bins.parse()?;
+    anyhow::ensure!(
+        bins_per_octave > 0,
+        "--cqt needs at least one bin per octave"
+    );
+    anyhow::ensure!(min > 0.0, "the --cqt minimum frequency must be positive");
+
+    Ok(Analysis::ConstantQ {
+        bins_per_octave,
+        min_frequency: min,
+    })
+}
+
+fn parse_tilt(input: &str) -> anyhow::Result<Tilt> {
+    let (slope, pivot) = match input.split_once(':') {
+        Some((slope, pivot)) => (slope.parse()?, pivot.parse()?),
+        None => (input.parse()?, Tilt::default().pivot),
+    };
+    anyhow::ensure!(pivot > 0.0, "the --tilt pivot must be positive");
+    Ok(Tilt { slope, pivot })
+}
+
+fn parse_weighting(input: &str) -> anyhow::Result<Weighting> {
+    Ok(match input.to_ascii_lowercase().as_str() {
+        "none" => Weighting::None,
+        "a" => Weighting::A,
+        "c" => Weighting::C,
+        "k" => Weighting::K,
+        "468" => Weighting::Itu468,
+        _ => anyhow::bail!("unknown weighting {input:?}, expected none, a, c, k or 468"),
+    })
+}
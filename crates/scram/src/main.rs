@@ -1,9 +1,26 @@
-use scram_capture::Context;
-use scram_process::{Processor, Slot, Source, config};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
-use mars_app::{Action, Application, Event, Renderer, Runner};
+use scram_capture::{
+    CaptureConfig, CaptureStats, Context, DeviceInfo, Generator, RawPcm, Recorder, Recording,
+    Status, WavFile, WavOptions,
+};
+use scram_process::{Band, Buffer, Processor, Resampler, SilenceState, Slot, Source, config};
+
+use mars_app::{Action, Application, BlendMode, Drawable as _, Event, Renderer, Runner};
+
+mod args;
+use args::{Args, RawOptions, RawSource};
 
 mod half_block;
+mod overlay;
+use overlay::Overlay;
+
 mod visualizer;
 use visualizer::Visualizer;
 
@@ -24,18 +41,166 @@ fn start_puffin() -> impl Drop {
     Noop
 }
 
+/// Set when the rolling recording should be written out, which SIGUSR1 asks for
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn listen_for_dump_requests() {
+    extern "C" fn request_dump(_signal: libc::c_int) {
+        DUMP_REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGUSR1, request_dump as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+fn listen_for_dump_requests() {}
+
+fn print_devices(devices: &[DeviceInfo]) {
+    for device in devices {
+        println!("[{}] {} ({})", device.index, device.name, device.host);
+
+        let defaults = [
+            ("input", device.default_input),
+            ("output", device.default_output),
+        ];
+        for (kind, format) in defaults {
+            let Some(format) = format else { continue };
+            println!(
+                "    default {kind}: {} ch, {} Hz, {}",
+                format.channels, format.sample_rate, format.sample_format
+            );
+        }
+
+        let supported = [
+            ("input", &device.supported_input),
+            ("output", &device.supported_output),
+        ];
+        for (kind, ranges) in supported {
+            for range in ranges {
+                println!(
+                    "    supported {kind}: {} ch, {}-{} Hz, {}",
+                    range.channels,
+                    range.min_sample_rate,
+                    range.max_sample_rate,
+                    range.sample_format
+                );
+            }
+        }
+    }
+}
+
+fn open_raw(raw: RawOptions, sample_size: usize) -> anyhow::Result<RawPcm> {
+    let RawOptions {
+        source,
+        format,
+        sample_rate,
+        channels,
+    } = raw;
+
+    match source {
+        RawSource::Stdin => RawPcm::stdin(format, sample_rate, channels, sample_size),
+        RawSource::Path(path) => RawPcm::open(path, format, sample_rate, channels, sample_size),
+        #[cfg(unix)]
+        RawSource::Fd(fd) => {
+            use std::os::fd::{FromRawFd as _, OwnedFd};
+            // SAFETY: the descriptor was handed to us to read from, and nothing else uses it
+            let file = std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+            RawPcm::new(file, format, sample_rate, channels, sample_size)
+        }
+        #[cfg(not(unix))]
+        RawSource::Fd(_) => anyhow::bail!("reading from a file descriptor needs a unix platform"),
+    }
+}
+
+struct Input {
+    source: Box<dyn Source>,
+    buffer: Box<dyn Buffer>,
+    /// Only devices report their status, files and generators can't go away
+    status: Option<flume::Receiver<Status>>,
+    stats: Option<CaptureStats>,
+}
+
+impl Input {
+    fn new(source: impl Source + 'static, buffer: impl Buffer) -> Self {
+        Self {
+            source: Box::new(source),
+            buffer: Box::new(buffer),
+            status: None,
+            stats: None,
+        }
+    }
+}
+
+fn open_input(args: Args, sample_size: usize) -> anyhow::Result<Input> {
+    if let Some(path) = &args.file {
+        let options = WavOptions {
+            pacing: args.pacing,
+            looping: args.looping,
+        };
+        let file = WavFile::open(path, sample_size, options)?;
+        return Ok(Input::new(file.source(), file));
+    }
+
+    if let Some(raw) = args.raw {
+        let raw = open_raw(raw, sample_size)?;
+        return Ok(Input::new(raw.source(), raw));
+    }
+
+    if !args.voices.is_empty() {
+        const GENERATOR_SAMPLE_RATE: u32 = 48_000;
+        let generator =
+            Generator::new(GENERATOR_SAMPLE_RATE, sample_size, args.voices, args.pacing)?;
+        return Ok(Input::new(generator.source(), generator));
+    }
+
+    let capture = CaptureConfig {
+        device: args.device,
+        mode: args.mode,
+    };
+    let (context, buffer) = Context::create(sample_size, &capture)?;
+    let (status, stats) = (context.status(), context.stats());
+    Ok(Input {
+        status: Some(status),
+        stats: Some(stats),
+        ..Input::new(context, buffer)
+    })
+}
+
+/// What the UI tells the processing thread
+enum Message {
+    Bands(usize),
+}
+
 fn main() -> anyhow::Result<()> {
+    let args = Args::parse()?;
+    if args.list_devices {
+        print_devices(&scram_capture::list_devices()?);
+        return Ok(());
+    }
+
     let _profile = start_puffin();
 
     let config = config::Config {
+        analysis: args.analysis,
         banding: config::Banding {
             frequency_cutoff: config::FrequencyCutoff {
                 low: 20.0,
                 high: 20000.0,
             },
-            scale: config::FrequencyScale::Mel,
+            scale: args.scale.clone(),
+            normalization: config::FilterNormalization::Peak,
+            fixed_bands: args.fixed_bands,
         },
         window: config::Window::Blackman,
+        weighting: args.weighting,
+        tilt: args.tilt,
+        zero_padding: 2,
+        hop: args.hop,
+        channel_map: args.channel_map,
         scaling: config::VolumeScale::Logarithimic,
         peak_smoothing: config::PeakSmoothing {
             attack_rate: 20.0,
@@ -45,49 +210,143 @@ fn main() -> anyhow::Result<()> {
         },
         band_smoothing: config::BandSmoothing::MovingAverage { window_size: 8 },
         // band_smoothing: config::BandSmoothing::Exponential { factor: 0.3 },
+        silence: config::Silence {
+            threshold: -60.0,
+            hold: 2.0,
+            behavior: config::SilenceBehavior::Decay,
+        },
     };
 
-    let sample_size = Processor::MAX_SAMPLE_SIZE;
-    let (source, mut buffer) = Context::create(sample_size)?;
+    let show_stats = args.stats;
+    let (record, record_last) = (args.record.clone(), args.record_last);
+    let analysis_rate = args.rate;
+    const DEFAULT_FFT_SIZE: usize = 2048;
+    let sample_size = args.fft_size.unwrap_or(DEFAULT_FFT_SIZE);
+    let Input {
+        source,
+        mut buffer,
+        status,
+        stats,
+    } = open_input(args, sample_size)?;
+
+    // a rolling recording only gets written out when asked for, or once the app is done
+    let mut recording = None;
+    // what went wrong along the way, for the overlay to show
+    let mut errors = Vec::new();
+    if let Some(path) = record {
+        let recorder = match record_last {
+            Some(duration) => {
+                let (recorder, handle) = Recorder::rolling(buffer, &*source, duration);
+                recording = Some((handle, path));
+                listen_for_dump_requests();
+                recorder
+            }
+            None => Recorder::to_file(buffer, &*source, path)?,
+        };
+        errors.push(recorder.errors());
+        buffer = Box::new(recorder);
+    }
+
+    let mut overlay = Overlay::default();
+    if let Some(stats) = stats.filter(|_| show_stats) {
+        overlay.show_stats(stats);
+    }
+
+    // recordings keep the original rate, only analysis sees the resampled stream. A device can
+    // come back at another rate, so the resampler stays even when the rates match for now
+    let (mut input, sample_rate): (Box<dyn Buffer>, _) = match analysis_rate {
+        Some(rate) => {
+            let (from, channels) = (source.sample_rate(), source.channels());
+            (
+                Box::new(Resampler::new(buffer, from, rate, channels)?),
+                rate,
+            )
+        }
+        None => (buffer, source.sample_rate()),
+    };
 
     let (tx, rx) = flume::unbounded();
 
-    let mut processor = Processor::new(source.sample_rate(), sample_size, config)?;
+    let mut processor = Processor::new(sample_rate, source.channels(), sample_size, config)?;
     let slot = Slot::default();
+    // set once the bars have fallen after the input went silent, so there's nothing to draw
+    let idle = Arc::new(AtomicBool::new(false));
+
+    let (errors_tx, processing_errors) = flume::unbounded();
+    errors.push(processing_errors);
 
     std::thread::spawn({
         let slot = slot.clone();
+        let idle = idle.clone();
         profiling::register_thread!("read samples");
         move || loop {
             match rx.try_recv() {
-                Ok(bands) => processor.set_bands(bands),
+                Ok(Message::Bands(bands)) => processor.set_bands(bands),
                 Err(flume::TryRecvError::Disconnected) => return,
                 _ => {}
             }
 
-            if processor.update(&mut buffer) {
+            let published = processor.update(&mut *input);
+            // a source that switched formats can leave the config falling back
+            if let Some(err) = processor.take_error() {
+                _ = errors_tx.send(err);
+            }
+            let silent = processor.silence() == SilenceState::Silent;
+            idle.store(silent && !published, Ordering::Relaxed);
+
+            if published {
                 profiling::scope!("put in current frequencies");
-                slot.put(processor.current_frequencies().map(<_>::to_owned));
+                slot.put(
+                    processor.current_frequencies().map(<_>::to_owned),
+                    processor.bands(),
+                );
+            } else if input.is_finished() {
+                return;
             }
         }
     });
 
     App {
         slot,
+        idle,
         tx,
+        errors,
         visualizer: Visualizer::new(),
-        _source: Box::new(source),
+        overlay,
+        status,
+        recording: recording.clone(),
+        bands: Arc::default(),
+        _source: source,
         dt: 0.0,
     }
     .run(60.0)?;
 
+    if let Some((recording, path)) = recording {
+        recording.dump(&path)?;
+        println!("{}", dumped(&recording, &path));
+    }
+
     Ok(())
 }
 
+fn dumped(recording: &Recording, path: &Path) -> String {
+    format!(
+        "wrote the last {:.1}s to {}",
+        recording.duration().as_secs_f64(),
+        path.display()
+    )
+}
+
 struct App {
     slot: Slot,
-    tx: flume::Sender<usize>,
+    idle: Arc<AtomicBool>,
+    tx: flume::Sender<Message>,
+    errors: Vec<flume::Receiver<anyhow::Error>>,
     visualizer: Visualizer,
+    overlay: Overlay,
+    status: Option<flume::Receiver<Status>>,
+    recording: Option<(Recording, PathBuf)>,
+    bands: Arc<[Band]>,
     _source: Box<dyn Source>,
     dt: f32,
 }
@@ -96,7 +355,7 @@ impl Application for App {
     fn event(&mut self, event: Event) -> Action {
         if let Event::Resize { size } = event {
             let bands = self.visualizer.axis().cross(size);
-            _ = self.tx.send(bands as usize);
+            _ = self.tx.send(Message::Bands(bands as usize));
             self.visualizer.resize(size);
         }
 
@@ -105,14 +364,42 @@ impl Application for App {
 
     fn update(&mut self, update: mars_app::Update) -> mars_app::ShouldRender {
         self.dt += update.dt;
+
+        for status in self.status.iter().flat_map(|status| status.try_iter()) {
+            self.overlay.set_status(status);
+        }
+
+        for err in self.errors.iter().flat_map(|errors| errors.try_iter()) {
+            self.overlay.set_message(format!("{err:#}"));
+        }
+
+        if DUMP_REQUESTED.swap(false, Ordering::Relaxed) {
+            if let Some((recording, path)) = &self.recording {
+                let message = match recording.dump(path) {
+                    Ok(()) => dumped(recording, path),
+                    Err(err) => format!("{err:#}"),
+                };
+                self.overlay.set_message(message);
+            }
+        }
+
         mars_app::ShouldRender::Yes
     }
 
     #[profiling::function]
     fn render(&mut self, renderer: &mut impl Renderer) {
         profiling::finish_frame!();
-        if let Some([left, right]) = self.slot.take() {
+        let bands = self.slot.bands();
+        if !Arc::ptr_eq(&bands, &self.bands) {
+            self.visualizer.set_bands(&bands);
+            self.bands = bands;
+        }
+
+        // the last frame is all zeros once it's idle, so drawing it again is wasted work
+        let idle = self.idle.load(Ordering::Relaxed);
+        if let Some([left, right]) = self.slot.take().filter(|_| !idle) {
             self.visualizer.draw(&left, &right, self.dt / 1.0, renderer);
         }
+        self.overlay.render(renderer, BlendMode::Replace);
     }
 }
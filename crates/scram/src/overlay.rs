@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+use mars_app::{BlendMode, Color, Drawable, Pixel, Placer, Position, Rgba, Size};
+use scram_capture::{CaptureStats, Status};
+
+/// Capture status, and optionally statistics, drawn over the top left corner of the visualization
+#[derive(Default)]
+pub struct Overlay {
+    status: Option<(Status, Instant)>,
+    message: Option<(String, Instant)>,
+    stats: Option<CaptureStats>,
+}
+
+impl Overlay {
+    /// How long a status stays up, unless capture is waiting on a device
+    const LINGER: Duration = Duration::from_secs(5);
+
+    const FOREGROUND: Rgba = Rgba::hex("#FFF");
+    const BACKGROUND: Rgba = Rgba::hex("#000");
+
+    pub fn set_status(&mut self, status: Status) {
+        self.status = Some((status, Instant::now()));
+    }
+
+    /// Shows a line of text for a little while
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some((message, Instant::now()));
+    }
+
+    pub fn show_stats(&mut self, stats: CaptureStats) {
+        self.stats = Some(stats);
+    }
+
+    fn status(&self) -> Option<String> {
+        let (status, since) = self.status.as_ref()?;
+        let visible =
+            matches!(status, Status::Reconnecting { .. }) || since.elapsed() < Self::LINGER;
+        visible.then(|| status.to_string())
+    }
+
+    fn message(&self) -> Option<String> {
+        let (message, since) = self.message.as_ref()?;
+        (since.elapsed() < Self::LINGER).then(|| message.clone())
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let stats = self
+            .stats
+            .as_ref()
+            .map(|stats| stats.snapshot().to_string());
+        self.status()
+            .into_iter()
+            .chain(self.message())
+            .chain(stats)
+            .collect()
+    }
+}
+
+impl Drawable for Overlay {
+    fn draw(&self, placer: &mut dyn Placer, pos: Position, _blend: BlendMode) {
+        let (fg, bg): (Color, Color) = (Self::FOREGROUND.into(), Self::BACKGROUND.into());
+        for (y, line) in (0..).zip(self.lines()) {
+            for (x, ch) in (0..).zip(line.chars()) {
+                let pixel = Pixel::new(ch).fg(fg).bg(bg);
+                placer.put(
+                    Position::new(pos.x + x, pos.y + y),
+                    pixel,
+                    BlendMode::Replace,
+                );
+            }
+        }
+    }
+
+    fn size(&self, _input: Size) -> Size {
+        let lines = self.lines();
+        let width = lines.iter().map(|line| line.chars().count()).max();
+        Size::new(width.unwrap_or(0) as u32, lines.len() as u32)
+    }
+}
@@ -1,6 +1,6 @@
 use mars_app::{Axis, BlendMode, Drawable as _, Renderer, Size};
 
-use scram_visualize::{Frequency, Visual, visualizers::*};
+use scram_visualize::{Band, Frequency, Visual, visualizers::*};
 
 use crate::half_block::HalfBlockRenderer;
 
@@ -28,6 +28,10 @@ impl Visualizer {
         self.spectro.resize(self.renderer.dimensions());
     }
 
+    pub fn set_bands(&mut self, bands: &[Band]) {
+        self.spectro.set_bands(bands);
+    }
+
     pub fn axis(&self) -> Axis {
         self.renderer.axis()
     }
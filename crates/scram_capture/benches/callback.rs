@@ -0,0 +1,47 @@
+//! What the audio callback costs: converting and boxing every callback into a channel,
+//! compared to converting straight into the preallocated ring
+
+use std::hint::black_box;
+
+use cpal::Sample as _;
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use scram_capture::ring;
+
+/// Interleaved samples per callback, for common buffer sizes
+const SIZES: [usize; 3] = [256, 1024, 4096];
+
+fn callback(c: &mut Criterion) {
+    let mut group = c.benchmark_group("callback");
+
+    for size in SIZES {
+        let input = (0..size)
+            .map(|i| (i as i16).wrapping_mul(257))
+            .collect::<Vec<_>>();
+
+        group.bench_with_input(BenchmarkId::new("boxed", size), &input, |b, input| {
+            let (tx, rx) = flume::bounded::<Box<[f32]>>(4);
+            b.iter(|| {
+                let samples = black_box(input)
+                    .iter()
+                    .map(|&sample| sample.to_sample::<f32>())
+                    .collect::<Box<[f32]>>();
+                _ = tx.try_send(samples);
+                // a reader that keeps up, which also pays for freeing the box
+                _ = rx.try_recv();
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("ring", size), &input, |b, input| {
+            let (mut producer, mut consumer) = ring::channel(size * 4);
+            b.iter(|| {
+                producer.push_map(black_box(input), |sample| sample.to_sample::<f32>());
+                consumer.skip(size);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, callback);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+use cpal::{FromSample, Sample};
+
+/// Converts a device sample to an `f32` normalized to `-1.0..=1.0`
+pub fn to_f32<T>(sample: T) -> f32
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    sample.to_sample::<f32>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn assert_converts<T>(input: &[T], expected: &[f32])
+    where
+        T: Sample + std::fmt::Debug,
+        f32: FromSample<T>,
+    {
+        let output = input
+            .iter()
+            .map(|&sample| to_f32(sample))
+            .collect::<Vec<_>>();
+        assert_eq!(output.len(), expected.len());
+        for (i, (a, b)) in output.iter().zip(expected).enumerate() {
+            assert!(
+                (a - b).abs() <= 1e-6,
+                "sample {i} of {input:?}: expected {b}, got {a}"
+            );
+        }
+    }
+
+    #[test]
+    fn signed() {
+        assert_converts(
+            &[i8::MIN, -64, 0, 64, i8::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 127.0 / 128.0],
+        );
+        assert_converts(
+            &[i16::MIN, -16384, 0, 16384, i16::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 32767.0 / 32768.0],
+        );
+        assert_converts(
+            &[i32::MIN, i32::MIN / 2, 0, i32::MAX / 2 + 1, i32::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 1.0],
+        );
+        assert_converts(
+            &[i64::MIN, i64::MIN / 2, 0, i64::MAX / 2 + 1, i64::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 1.0],
+        );
+    }
+
+    #[test]
+    fn unsigned() {
+        assert_converts(
+            &[u8::MIN, 64, 128, 192, u8::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 127.0 / 128.0],
+        );
+        assert_converts(
+            &[u16::MIN, 16384, 32768, 49152, u16::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 32767.0 / 32768.0],
+        );
+        assert_converts(
+            &[u32::MIN, 1 << 30, 1 << 31, 3 << 30, u32::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 1.0],
+        );
+        assert_converts(
+            &[u64::MIN, 1 << 62, 1 << 63, 3 << 62, u64::MAX],
+            &[-1.0, -0.5, 0.0, 0.5, 1.0],
+        );
+    }
+
+    #[test]
+    fn float() {
+        assert_converts(
+            &[-1.0_f32, -0.25, 0.0, 0.25, 1.0],
+            &[-1.0, -0.25, 0.0, 0.25, 1.0],
+        );
+        assert_converts(
+            &[-1.0_f64, -0.25, 0.0, 0.25, 1.0],
+            &[-1.0, -0.25, 0.0, 0.25, 1.0],
+        );
+    }
+}
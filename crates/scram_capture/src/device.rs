@@ -0,0 +1,196 @@
+use anyhow::Context as _;
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::CaptureMode;
+
+pub use cpal::SampleFormat;
+
+/// How the capture device is chosen
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceSelector {
+    /// The host's default device
+    #[default]
+    Default,
+    /// The device at this position in [`list_devices`]
+    Index(usize),
+    /// The device with exactly this name, otherwise the only device whose name contains it (case-insensitive)
+    Name(String),
+}
+
+impl std::str::FromStr for DeviceSelector {
+    type Err = std::convert::Infallible;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let selector = match input {
+            "default" => Self::Default,
+            input => input
+                .parse()
+                .map(Self::Index)
+                .unwrap_or_else(|_| Self::Name(input.to_string())),
+        };
+        Ok(selector)
+    }
+}
+
+impl std::fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::Index(index) => write!(f, "#{index}"),
+            Self::Name(name) => write!(f, "{name:?}"),
+        }
+    }
+}
+
+/// A concrete stream format a device can be opened with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub sample_format: SampleFormat,
+}
+
+impl From<&cpal::SupportedStreamConfig> for StreamFormat {
+    fn from(config: &cpal::SupportedStreamConfig) -> Self {
+        Self {
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+            sample_format: config.sample_format(),
+        }
+    }
+}
+
+/// A range of stream formats a device supports
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FormatRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: SampleFormat,
+}
+
+impl From<cpal::SupportedStreamConfigRange> for FormatRange {
+    fn from(range: cpal::SupportedStreamConfigRange) -> Self {
+        Self {
+            channels: range.channels(),
+            min_sample_rate: range.min_sample_rate().0,
+            max_sample_rate: range.max_sample_rate().0,
+            sample_format: range.sample_format(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Position of this device, usable with [`DeviceSelector::Index`]
+    pub index: usize,
+    pub name: String,
+    pub host: &'static str,
+    pub default_input: Option<StreamFormat>,
+    pub default_output: Option<StreamFormat>,
+    pub supported_input: Vec<FormatRange>,
+    pub supported_output: Vec<FormatRange>,
+}
+
+/// Lists every device the default host knows about
+pub fn list_devices() -> anyhow::Result<Vec<DeviceInfo>> {
+    let host = cpal::default_host();
+    let host_name = host.id().name();
+
+    let devices = host
+        .devices()
+        .with_context(|| "cannot enumerate devices")?
+        .enumerate()
+        .map(|(index, device)| DeviceInfo {
+            index,
+            name: device_name(&device),
+            host: host_name,
+            default_input: device
+                .default_input_config()
+                .ok()
+                .as_ref()
+                .map(StreamFormat::from),
+            default_output: device
+                .default_output_config()
+                .ok()
+                .as_ref()
+                .map(StreamFormat::from),
+            supported_input: device
+                .supported_input_configs()
+                .map(|configs| configs.map(FormatRange::from).collect())
+                .unwrap_or_default(),
+            supported_output: device
+                .supported_output_configs()
+                .map(|configs| configs.map(FormatRange::from).collect())
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(devices)
+}
+
+pub(crate) fn default_config(
+    device: &cpal::Device,
+    mode: CaptureMode,
+) -> anyhow::Result<cpal::SupportedStreamConfig> {
+    let config = match mode {
+        CaptureMode::Loopback => device.default_output_config(),
+        CaptureMode::Input => device.default_input_config(),
+    };
+    config.with_context(|| {
+        let name = device_name(device);
+        format!("cannot get the default {mode} config for {name:?}")
+    })
+}
+
+pub(crate) fn device_name(device: &cpal::Device) -> String {
+    device.name().unwrap_or_else(|_| String::from("<unknown>"))
+}
+
+pub(crate) fn find_device(
+    host: &cpal::Host,
+    selector: &DeviceSelector,
+    mode: CaptureMode,
+) -> anyhow::Result<cpal::Device> {
+    let name = match selector {
+        DeviceSelector::Default => {
+            let device = match mode {
+                CaptureMode::Loopback => host.default_output_device(),
+                CaptureMode::Input => host.default_input_device(),
+            };
+            return device.with_context(|| anyhow::anyhow!("no default {mode} device"));
+        }
+        DeviceSelector::Index(index) => {
+            return host
+                .devices()?
+                .nth(*index)
+                .with_context(|| anyhow::anyhow!("no device at index {index}"));
+        }
+        DeviceSelector::Name(name) => name,
+    };
+
+    let mut devices = host
+        .devices()?
+        .map(|device| (device_name(&device), device))
+        .collect::<Vec<_>>();
+
+    if let Some(pos) = devices.iter().position(|(n, _)| n == name) {
+        return Ok(devices.swap_remove(pos).1);
+    }
+
+    let needle = name.to_lowercase();
+    devices.retain(|(n, _)| n.to_lowercase().contains(&needle));
+
+    match devices.len() {
+        0 => anyhow::bail!("no device matches {name:?}"),
+        1 => Ok(devices.remove(0).1),
+        _ => {
+            let names = devices
+                .iter()
+                .map(|(n, _)| format!("{n:?}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::bail!("{name:?} matches more than one device: {names}")
+        }
+    }
+}
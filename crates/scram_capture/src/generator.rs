@@ -0,0 +1,414 @@
+use std::{f64::consts::TAU, time::Duration};
+
+use scram_process::{Buffer, Source};
+
+use crate::{Pacing, SourceInfo, pacing::Pacer};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SweepScale {
+    Linear,
+    #[default]
+    Logarithmic,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Signal {
+    Silence,
+    Sine {
+        frequency: f32,
+    },
+    /// Glides from `start` to `end` Hz over `duration`, then starts over
+    Sweep {
+        start: f32,
+        end: f32,
+        duration: Duration,
+        scale: SweepScale,
+    },
+    WhiteNoise,
+    /// Noise with equal energy per octave
+    PinkNoise,
+    /// A single full-scale sample every `interval`
+    Impulse {
+        interval: Duration,
+    },
+    Square {
+        frequency: f32,
+    },
+    Saw {
+        frequency: f32,
+    },
+    /// Equal-level sines mixed together
+    Chord {
+        frequencies: Vec<f32>,
+    },
+}
+
+/// A signal, and how loud it is, for one channel
+#[derive(Clone, Debug, PartialEq)]
+pub struct Voice {
+    pub signal: Signal,
+    pub amplitude: f32,
+}
+
+impl Voice {
+    pub const fn new(signal: Signal) -> Self {
+        Self {
+            signal,
+            amplitude: 1.0,
+        }
+    }
+}
+
+impl std::str::FromStr for Voice {
+    type Err = anyhow::Error;
+
+    /// Parses `<signal>[@<amplitude>]`, where signal is one of
+    ///
+    /// `silence`, `white`, `pink`, `sine:<hz>`, `square:<hz>`, `saw:<hz>`,
+    /// `impulse:<seconds>`, `chord:<hz>,<hz>,..` or `sweep:<hz>:<hz>:<seconds>[:lin|:log]`
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        fn number<T: std::str::FromStr>(input: Option<&str>, what: &str) -> anyhow::Result<T> {
+            let input = input.ok_or_else(|| anyhow::anyhow!("missing {what}"))?;
+            input
+                .trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid {what}: {input:?}"))
+        }
+
+        fn seconds(input: Option<&str>, what: &str) -> anyhow::Result<Duration> {
+            let secs = number::<f64>(input, what)?;
+            anyhow::ensure!(secs > 0.0, "{what} must be positive");
+            Ok(Duration::from_secs_f64(secs))
+        }
+
+        let (signal, amplitude) = match input.split_once('@') {
+            Some((signal, amplitude)) => (signal, number(Some(amplitude), "amplitude")?),
+            None => (input, 1.0),
+        };
+
+        let mut parts = signal.split(':');
+        let signal = match parts.next().unwrap_or_default() {
+            "silence" => Signal::Silence,
+            "white" => Signal::WhiteNoise,
+            "pink" => Signal::PinkNoise,
+            "sine" => Signal::Sine {
+                frequency: number(parts.next(), "frequency")?,
+            },
+            "square" => Signal::Square {
+                frequency: number(parts.next(), "frequency")?,
+            },
+            "saw" => Signal::Saw {
+                frequency: number(parts.next(), "frequency")?,
+            },
+            "impulse" => Signal::Impulse {
+                interval: seconds(parts.next(), "interval")?,
+            },
+            "chord" => Signal::Chord {
+                frequencies: parts
+                    .next()
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|freq| number(Some(freq), "frequency"))
+                    .collect::<anyhow::Result<_>>()?,
+            },
+            "sweep" => Signal::Sweep {
+                start: number(parts.next(), "start frequency")?,
+                end: number(parts.next(), "end frequency")?,
+                duration: seconds(parts.next(), "duration")?,
+                scale: match parts.next() {
+                    None | Some("log") => SweepScale::Logarithmic,
+                    Some("lin") => SweepScale::Linear,
+                    Some(scale) => anyhow::bail!("unknown sweep scale: {scale:?}"),
+                },
+            },
+            signal => anyhow::bail!("unknown signal: {signal:?}"),
+        };
+
+        if let Signal::Sweep {
+            start,
+            end,
+            scale: SweepScale::Logarithmic,
+            ..
+        } = signal
+        {
+            anyhow::ensure!(
+                start > 0.0 && end > 0.0,
+                "a logarithmic sweep needs positive frequencies"
+            );
+        }
+
+        if let Some(rest) = parts.next() {
+            anyhow::bail!("unexpected {rest:?} in {input:?}")
+        }
+
+        Ok(Self { signal, amplitude })
+    }
+}
+
+struct Oscillator {
+    voice: Voice,
+    rng: u64,
+    pink: [f64; 7],
+}
+
+impl Oscillator {
+    fn new(voice: Voice, seed: u64) -> Self {
+        Self {
+            voice,
+            // xorshift must not start at zero
+            rng: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            pink: [0.0; 7],
+        }
+    }
+
+    fn white(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1_u64 << 52) as f64 - 1.0
+    }
+
+    /// Paul Kellet's refined pink noise filter
+    fn pink(&mut self) -> f64 {
+        let white = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.1538520;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * 0.11
+    }
+
+    /// The sample at `frame`, with time derived from the frame so phase never drifts
+    fn sample(&mut self, frame: u64, sample_rate: f64) -> f32 {
+        let time = frame as f64 / sample_rate;
+        let cycles = |frequency: f32| (frequency as f64 * time).fract();
+
+        let sample = match &self.voice.signal {
+            Signal::Silence => 0.0,
+            &Signal::Sine { frequency } => (TAU * cycles(frequency)).sin(),
+            &Signal::Square { frequency } => match cycles(frequency) {
+                ..0.5 => 1.0,
+                _ => -1.0,
+            },
+            &Signal::Saw { frequency } => 2.0 * cycles(frequency) - 1.0,
+            Signal::Chord { frequencies } if frequencies.is_empty() => 0.0,
+            Signal::Chord { frequencies } => {
+                let sum = frequencies
+                    .iter()
+                    .map(|&frequency| (TAU * cycles(frequency)).sin())
+                    .sum::<f64>();
+                sum / frequencies.len() as f64
+            }
+            &Signal::Sweep {
+                start,
+                end,
+                duration,
+                scale,
+            } => {
+                let (start, end) = (start as f64, end as f64);
+                let duration = duration.as_secs_f64();
+                let t = time % duration;
+
+                // phase is the integral of the instantaneous frequency
+                let phase = match scale {
+                    SweepScale::Linear => start * t + (end - start) * t * t / (2.0 * duration),
+                    SweepScale::Logarithmic if start == end => start * t,
+                    SweepScale::Logarithmic => {
+                        let rate = (end / start).ln() / duration;
+                        start * ((rate * t).exp() - 1.0) / rate
+                    }
+                };
+                (TAU * phase.fract()).sin()
+            }
+            &Signal::Impulse { interval } => {
+                let interval = (interval.as_secs_f64() * sample_rate).round().max(1.0) as u64;
+                if frame % interval == 0 { 1.0 } else { 0.0 }
+            }
+            Signal::WhiteNoise => self.white(),
+            Signal::PinkNoise => self.pink(),
+        };
+
+        sample as f32 * self.voice.amplitude
+    }
+}
+
+/// Known test signals, one per channel, read as if they came from a device
+pub struct Generator {
+    oscillators: Vec<Oscillator>,
+    window: Vec<f32>,
+    frame: u64,
+    pacer: Pacer,
+    info: SourceInfo,
+}
+
+impl Generator {
+    pub fn new(
+        sample_rate: u32,
+        sample_size: usize,
+        voices: impl IntoIterator<Item = Voice>,
+        pacing: Pacing,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(sample_rate > 0, "the sample rate must be positive");
+
+        let oscillators = voices
+            .into_iter()
+            .zip(1..)
+            .map(|(voice, seed)| Oscillator::new(voice, seed))
+            .collect::<Vec<_>>();
+
+        anyhow::ensure!(!oscillators.is_empty(), "at least one voice is required");
+        let channels = u16::try_from(oscillators.len())?;
+
+        Ok(Self {
+            oscillators,
+            window: Vec::with_capacity(sample_size),
+            frame: 0,
+            pacer: Pacer::new(pacing, sample_rate),
+            info: SourceInfo {
+                sample_rate,
+                sample_size,
+                channels,
+            },
+        })
+    }
+
+    /// The format of this generator, which outlives it being moved into a processing thread
+    pub fn source(&self) -> SourceInfo {
+        self.info
+    }
+}
+
+impl Buffer for Generator {
+    #[profiling::function]
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+        let channels = self.oscillators.len();
+
+        // keep the tail of the last window, and only generate what's new
+        let keep = sample_size.saturating_sub(hop_size).min(self.window.len());
+        self.window.drain(..self.window.len() - keep);
+
+        let frames = (sample_size - self.window.len()) / channels;
+        self.pacer.wait(frames);
+
+        let sample_rate = self.info.sample_rate as f64;
+        for frame in self.frame..self.frame + frames as u64 {
+            for oscillator in &mut self.oscillators {
+                self.window.push(oscillator.sample(frame, sample_rate));
+            }
+        }
+        self.frame += frames as u64;
+
+        Some(&self.window)
+    }
+}
+
+impl Source for Generator {
+    fn sample_rate(&self) -> u32 {
+        self.info.sample_rate
+    }
+
+    fn sample_size(&self) -> usize {
+        self.info.sample_size
+    }
+
+    fn channels(&self) -> u16 {
+        self.info.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use scram_process::{
+        Processor,
+        config::{
+            BandSmoothing, Banding, Config, FrequencyCutoff, FrequencyScale, PeakSmoothing, Tilt,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn tone_lands_in_its_band() {
+        const SAMPLE_RATE: u32 = 48_000;
+        const SAMPLE_SIZE: usize = 1024;
+
+        let voice = "sine:1000@0.1".parse().unwrap();
+        let mut generator =
+            Generator::new(SAMPLE_RATE, SAMPLE_SIZE, [voice], Pacing::Unpaced).unwrap();
+        let mut processor = Processor::new(SAMPLE_RATE, 1, SAMPLE_SIZE, Config::default()).unwrap();
+        processor.set_bands(64);
+
+        // let the bars rise all the way
+        for _ in 0..32 {
+            assert!(processor.update(&mut generator));
+        }
+
+        let [left, _] = processor.current_frequencies();
+        let loudest = (0..left.len())
+            .max_by(|&a, &b| left[a].value.total_cmp(&left[b].value))
+            .unwrap();
+        let band = processor.bands()[loudest];
+        assert!(
+            (band.low..=band.high).contains(&1000.0),
+            "the loudest band, {loudest}, covers {band:?}"
+        );
+    }
+
+    #[test]
+    fn tilted_pink_noise_comes_out_flat() {
+        const SAMPLE_RATE: u32 = 48_000;
+        const SAMPLE_SIZE: usize = 1024;
+        const WINDOWS: usize = 64;
+
+        let config = Config {
+            banding: Banding {
+                frequency_cutoff: FrequencyCutoff {
+                    low: 100.0,
+                    high: 16_000.0,
+                },
+                scale: FrequencyScale::Logarithmic,
+                ..Banding::default()
+            },
+            tilt: Tilt {
+                slope: -3.0,
+                ..Tilt::default()
+            },
+            band_smoothing: BandSmoothing::None,
+            // bars follow every window, so averaging them averages the bands
+            peak_smoothing: PeakSmoothing {
+                attack_rate: 1e9,
+                decay_rate: 1e9,
+                decay_limit: 1e-9,
+                ..PeakSmoothing::default()
+            },
+            ..Config::default()
+        };
+
+        let voice = "pink@0.5".parse().unwrap();
+        let mut generator =
+            Generator::new(SAMPLE_RATE, SAMPLE_SIZE, [voice], Pacing::Unpaced).unwrap();
+        let mut processor = Processor::new(SAMPLE_RATE, 1, SAMPLE_SIZE, config).unwrap();
+        processor.set_bands(8);
+
+        let mut sums = [0.0; 8];
+        for _ in 0..WINDOWS {
+            assert!(processor.update(&mut generator));
+            let [left, _] = processor.current_frequencies();
+            for (sum, bar) in sums.iter_mut().zip(left) {
+                *sum += bar.value;
+            }
+        }
+
+        // bars go from -60 to 0 dB
+        let levels = sums.map(|sum| sum / WINDOWS as f32 * 60.0 - 60.0);
+        let (quietest, loudest) = levels.iter().fold((f32::MAX, f32::MIN), |(min, max), &db| {
+            (min.min(db), max.max(db))
+        });
+        assert!(loudest - quietest < 1.5, "{levels:?}");
+    }
+}
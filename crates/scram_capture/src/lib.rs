@@ -1,90 +1,449 @@
-use std::collections::VecDeque;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::Context as _;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use scram_process::{Buffer, Format, Source};
 
-use scram_process::{Buffer, Source};
+mod convert;
+
+mod device;
+pub use device::{
+    DeviceInfo, DeviceSelector, FormatRange, SampleFormat, StreamFormat, list_devices,
+};
+
+mod generator;
+pub use generator::{Generator, Signal, SweepScale, Voice};
+
+mod pacing;
+pub use pacing::Pacing;
+
+mod raw;
+pub use raw::{PcmFormat, RawPcm};
+
+mod record;
+pub use record::{Recorder, Recording};
+
+// only public for the benchmarks
+#[doc(hidden)]
+pub mod ring;
+
+mod stats;
+pub use stats::{CaptureStats, Stats};
+
+mod stream;
+pub use stream::Status;
+
+mod wav;
+pub use wav::{WavFile, WavOptions};
+
+/// The format of a source that doesn't need anything kept alive
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SourceInfo {
+    pub sample_rate: u32,
+    pub sample_size: usize,
+    pub channels: u16,
+}
+
+impl Source for SourceInfo {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn sample_size(&self) -> usize {
+        self.sample_size
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+}
 
 pub struct CpalBuffer {
-    rx: flume::Receiver<Box<[f32]>>,
-    buffer: VecDeque<f32>,
+    consumer: ring::Consumer,
+    /// Samples taken out of the ring, the current window starts at `start`
+    window: Vec<f32>,
+    start: usize,
+    counters: Arc<stats::Counters>,
+    /// Interleaved samples the device produces per second
+    samples_per_second: f64,
+    primed: bool,
+    /// Samples handed out by the last stream read, which the next read moves past
+    streamed: usize,
+    next_due: Instant,
+    format: Format,
+    /// Formats of the streams connected to, each sent before the stream's first sample
+    formats: flume::Receiver<StreamFormat>,
+}
+
+impl CpalBuffer {
+    /// How long to wait for the device, at most, before checking on it again
+    const MAX_POLL: Duration = Duration::from_millis(20);
+
+    fn buffered(&self) -> usize {
+        self.window.len() - self.start
+    }
+
+    /// Throws away the oldest samples, from the window first and then from the ring
+    fn discard(&mut self, count: usize) {
+        let from_window = count.min(self.buffered());
+        self.start += from_window;
+        self.consumer.skip(count - from_window);
+    }
+
+    /// Moves samples out of the ring until a whole window is buffered, or the device goes away
+    /// or switches formats
+    fn fill(&mut self, sample_size: usize) -> Option<()> {
+        // there's always room for a window, so only move what's left over to the front
+        let capacity = 2 * sample_size;
+        if self.window.capacity() < capacity {
+            self.window.reserve(capacity - self.window.len());
+        }
+
+        while self.buffered() < sample_size {
+            let wanted = sample_size - self.buffered();
+            if self.window.len() + wanted > capacity {
+                self.window.copy_within(self.start.., 0);
+                self.window.truncate(self.buffered());
+                self.start = 0;
+            }
+
+            let len = self.window.len();
+            self.window.resize(len + wanted, 0.0);
+            let read = self.consumer.pop(&mut self.window[len..]);
+            self.window.truncate(len + read);
+
+            // the format is sent before the samples, so any in the new one are seen with it
+            if !self.formats.is_empty() {
+                return None;
+            }
+            if read < wanted {
+                if self.consumer.is_abandoned() {
+                    return None;
+                }
+                let missing = (wanted - read) as f64 / self.samples_per_second;
+                let wait = Duration::from_secs_f64(missing).min(Self::MAX_POLL);
+                std::thread::sleep(wait.max(Duration::from_millis(1)));
+            }
+        }
+
+        Some(())
+    }
+
+    /// Moves everything left in the ring behind what's buffered
+    fn drain(&mut self) {
+        self.window.drain(..self.start);
+        self.start = 0;
+
+        let len = self.window.len();
+        self.window.resize(len + self.consumer.len(), 0.0);
+        let read = self.consumer.pop(&mut self.window[len..]);
+        self.window.truncate(len + read);
+    }
+
+    /// Moves past whatever the last stream read handed out
+    fn start_read(&mut self) {
+        self.start += std::mem::take(&mut self.streamed);
+    }
+
+    /// The end of what was read is as old as the last callback, plus whatever came after it
+    fn update_latency(&self) {
+        let queued = self.consumer.len();
+        let backlog = Duration::from_secs_f64(queued as f64 / self.samples_per_second);
+        self.counters
+            .set_latency(self.counters.since_last_callback() + backlog);
+        self.counters.set_queued(queued);
+    }
 }
 
 impl Buffer for CpalBuffer {
     #[profiling::function]
-    fn read_samples(&mut self, sample_size: usize) -> Option<&[f32]> {
-        let data = self.rx.recv().ok()?;
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+        let hop_size = hop_size.clamp(1, sample_size.max(1));
+        self.start_read();
 
-        profiling::scope!("append data");
-        let current = self.buffer.len();
-        let delta = current
-            .saturating_add(data.len())
-            .saturating_sub(sample_size);
+        if std::mem::replace(&mut self.primed, true) {
+            self.discard(hop_size);
+        }
 
-        self.buffer.drain(..delta);
-        self.buffer.extend(data);
+        // when falling more than a window behind the device, skip whole hops to catch up
+        let pending = self.buffered() + self.consumer.len();
+        if pending > 2 * sample_size {
+            let backlog = pending - sample_size;
+            let skip = (backlog - sample_size).div_ceil(hop_size) * hop_size;
+            let skip = skip.min(backlog);
+            self.discard(skip);
+            self.counters.dropped(skip);
+            self.next_due = Instant::now();
+        }
 
-        if self.buffer.len() == sample_size {
-            profiling::scope!("vecdeque to slice");
-            return Some(self.buffer.make_contiguous());
+        {
+            profiling::scope!("append data");
+            self.fill(sample_size)?;
         }
 
-        None
+        // callbacks can deliver several hops at once, so spread them out over time
+        let now = Instant::now();
+        if let Some(wait) = self.next_due.checked_duration_since(now) {
+            profiling::scope!("pace windows");
+            std::thread::sleep(wait);
+        }
+        let hop_time = Duration::from_secs_f64(hop_size as f64 / self.samples_per_second);
+        self.next_due = self.next_due.max(now) + hop_time;
+
+        self.update_latency();
+        Some(&self.window[self.start..self.start + sample_size])
+    }
+
+    #[profiling::function]
+    fn read_stream(&mut self, min_size: usize) -> Option<&[f32]> {
+        self.start_read();
+        // windows read after this start over
+        self.primed = false;
+
+        self.fill(min_size)?;
+        self.drain();
+        if !self.formats.is_empty() {
+            return None;
+        }
+
+        self.update_latency();
+        self.streamed = self.buffered();
+        Some(&self.window[self.start..])
+    }
+
+    fn report_dropped(&mut self, samples: usize) {
+        self.counters.dropped(samples);
+    }
+
+    fn next_format(&mut self) -> Option<Format> {
+        let stream = self.formats.try_iter().last()?;
+
+        // what's queued is from before the switch, or mixed up with what came after it
+        self.start_read();
+        self.discard(self.buffered() + self.consumer.len());
+        self.primed = false;
+
+        let format = Format {
+            sample_rate: stream.sample_rate,
+            channels: stream.channels,
+        };
+        self.samples_per_second = format.sample_rate as f64 * format.channels as f64;
+        // a device can come back just as it was
+        (std::mem::replace(&mut self.format, format) != format).then_some(format)
     }
 }
 
-pub struct Context {
-    _stream: cpal::Stream,
-    sample_rate: cpal::SampleRate,
-    sample_size: usize,
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// Record what an output device is playing
+    #[default]
+    Loopback,
+    /// Record from an input device, such as a microphone or a line-in
+    Input,
 }
 
-impl Context {
-    pub fn create(sample_size: usize) -> anyhow::Result<(impl Source, CpalBuffer)> {
-        let host = cpal::default_host();
+impl std::fmt::Display for CaptureMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Loopback => "output",
+            Self::Input => "input",
+        })
+    }
+}
 
-        let output = host
-            .default_output_device()
-            .with_context(|| anyhow::anyhow!("no default output device"))?;
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CaptureConfig {
+    pub device: DeviceSelector,
+    pub mode: CaptureMode,
+}
 
-        let config = output.default_output_config()?;
-        let config = config.config();
+pub struct Context {
+    control: flume::Sender<stream::Control>,
+    status: flume::Receiver<Status>,
+    stats: CaptureStats,
+    device_name: String,
+    mode: CaptureMode,
+    sample_rate: u32,
+    channels: u16,
+    sample_size: usize,
+}
 
-        let (tx, rx) = flume::bounded(4); // gave it some headroom
-        let stream = output.build_input_stream(
-            &config,
-            move |data: &[f32], _| {
-                _ = tx.send(Box::from(data));
-            },
-            |err| eprintln!("cpal input stream read err: {err}"),
-            None,
-        )?;
+impl Context {
+    /// Windows the ring holds before the device has to drop samples
+    const WINDOWS: usize = 16;
+    /// Channels the ring is sized for, as many as 7.1 has
+    const MAX_CHANNELS: usize = 8;
 
-        stream
-            .play()
-            .with_context(|| "cannot start output stream")?;
+    /// Starts capturing, and keeps reconnecting in the background when the device goes away
+    pub fn create(
+        sample_size: usize,
+        capture: &CaptureConfig,
+    ) -> anyhow::Result<(Self, CpalBuffer)> {
+        // the ring counts interleaved samples and `sample_size` counts frames, so make room for
+        // the windows at the most channels a device could come back with
+        let (producer, consumer) = ring::channel(sample_size * Self::WINDOWS * Self::MAX_CHANNELS);
+        let (format_tx, format_rx) = flume::unbounded();
+        let counters = Arc::<stats::Counters>::default();
+        let capture_thread = stream::spawn(capture.clone(), producer, format_tx, counters.clone())?;
+        let format = capture_thread.format;
 
         let handle = CpalBuffer {
-            buffer: VecDeque::with_capacity(sample_size),
-            rx,
+            consumer,
+            window: Vec::with_capacity(2 * sample_size),
+            start: 0,
+            samples_per_second: format.sample_rate as f64 * format.channels as f64,
+            primed: false,
+            streamed: 0,
+            next_due: Instant::now(),
+            format: Format {
+                sample_rate: format.sample_rate,
+                channels: format.channels,
+            },
+            formats: format_rx,
+            counters: counters.clone(),
         };
 
         let this = Self {
-            _stream: stream,
-            sample_rate: config.sample_rate,
+            control: capture_thread.control,
+            status: capture_thread.status,
+            stats: CaptureStats { counters },
+            device_name: capture_thread.device,
+            mode: capture.mode,
+            sample_rate: format.sample_rate,
+            channels: format.channels,
             sample_size,
         };
 
         Ok((this, handle))
     }
+
+    /// Name of the device capture started with
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    pub fn mode(&self) -> CaptureMode {
+        self.mode
+    }
+
+    /// Connection changes and device errors, as they happen
+    pub fn status(&self) -> flume::Receiver<Status> {
+        self.status.clone()
+    }
+
+    /// Counters for how healthy capture is, readable from any thread
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.clone()
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        _ = self.control.send(stream::Control::Shutdown);
+    }
 }
 
 impl Source for Context {
     fn sample_rate(&self) -> u32 {
-        self.sample_rate.0
+        self.sample_rate
     }
 
     fn sample_size(&self) -> usize {
         self.sample_size
     }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_format(sample_rate: u32, channels: u16) -> StreamFormat {
+        StreamFormat {
+            channels,
+            sample_rate,
+            sample_format: SampleFormat::F32,
+        }
+    }
+
+    /// A mono buffer at 48 kHz, with what feeds it
+    fn mono() -> (ring::Producer, flume::Sender<StreamFormat>, CpalBuffer) {
+        let (producer, consumer) = ring::channel(1 << 12);
+        let (formats, format_rx) = flume::unbounded();
+        let buffer = CpalBuffer {
+            consumer,
+            window: Vec::new(),
+            start: 0,
+            counters: Arc::default(),
+            samples_per_second: 48_000.0,
+            primed: false,
+            streamed: 0,
+            next_due: Instant::now(),
+            format: Format {
+                sample_rate: 48_000,
+                channels: 1,
+            },
+            formats: format_rx,
+        };
+        (producer, formats, buffer)
+    }
+
+    #[test]
+    fn windows_come_out_a_hop_apart_whatever_the_callbacks() {
+        let (mut producer, _formats, mut buffer) = mono();
+        let (sample_size, hop_size) = (64, 16);
+        let callbacks = [5, 37, 1, 23, 16];
+
+        let (mut pushed, mut windows) = (0, 0);
+        for callback in callbacks.iter().cycle().take(256) {
+            // every sample is its own index, so each window says where it starts
+            let end = pushed + callback;
+            producer.push(&(pushed..end).map(|n| n as f32).collect::<Vec<_>>());
+            pushed = end;
+
+            // read whatever windows the callback completed
+            while windows * hop_size + sample_size <= pushed {
+                let start = windows * hop_size;
+                let window = buffer.read_samples(sample_size, hop_size).unwrap();
+                let expected = (start..start + sample_size).map(|n| n as f32);
+                assert!(window.iter().copied().eq(expected), "window {windows}");
+                windows += 1;
+            }
+        }
+        assert!(windows > 200);
+    }
+
+    #[test]
+    fn windows_dont_straddle_a_format_switch() {
+        let (mut producer, formats, mut buffer) = mono();
+
+        // a mono device goes away halfway through a window, and comes back in stereo
+        producer.push(&[1.0; 96]);
+        formats.send(stream_format(44_100, 2)).unwrap();
+        producer.push(&[2.0; 96]);
+        assert!(buffer.read_samples(128, 128).is_none());
+
+        let stereo = Format {
+            sample_rate: 44_100,
+            channels: 2,
+        };
+        assert_eq!(buffer.next_format(), Some(stereo));
+        assert_eq!(buffer.next_format(), None);
+
+        producer.push(&[2.0; 128]);
+        let window = buffer.read_samples(128, 128).unwrap();
+        assert!(window.iter().all(|&sample| sample == 2.0));
+
+        // coming back in the same format still throws away what's stale, but isn't a switch
+        producer.push(&[1.0; 64]);
+        formats.send(stream_format(44_100, 2)).unwrap();
+        assert_eq!(buffer.next_format(), None);
+        producer.push(&[3.0; 128]);
+        let window = buffer.read_samples(128, 128).unwrap();
+        assert!(window.iter().all(|&sample| sample == 3.0));
+    }
 }
@@ -0,0 +1,55 @@
+use std::time::{Duration, Instant};
+
+/// How quickly a non-device source hands out samples
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Pacing {
+    /// No faster than they would be played back, following the wall clock
+    #[default]
+    RealTime,
+    /// As fast as they are asked for
+    Unpaced,
+}
+
+pub(crate) struct Pacer {
+    pacing: Pacing,
+    sample_rate: u32,
+    start: Instant,
+    frames: u64,
+}
+
+impl Pacer {
+    /// Falling further behind than this restarts the clock instead of bursting to catch up
+    const MAX_LAG: Duration = Duration::from_millis(250);
+
+    pub fn new(pacing: Pacing, sample_rate: u32) -> Self {
+        Self {
+            pacing,
+            sample_rate,
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.start = Instant::now();
+        self.frames = 0;
+    }
+
+    /// Blocks until `frames` more frames would have been played
+    pub fn wait(&mut self, frames: usize) {
+        if self.pacing == Pacing::Unpaced {
+            return;
+        }
+
+        self.frames += frames as u64;
+        let due =
+            self.start + Duration::from_secs_f64(self.frames as f64 / self.sample_rate as f64);
+
+        let now = Instant::now();
+        match due.checked_duration_since(now) {
+            Some(remaining) => std::thread::sleep(remaining),
+            None if now.duration_since(due) > Self::MAX_LAG => self.reset(),
+            None => {}
+        }
+    }
+}
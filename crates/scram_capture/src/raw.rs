@@ -0,0 +1,236 @@
+use std::{io::Read, path::Path};
+
+use anyhow::Context as _;
+
+use scram_process::{Buffer, Source};
+
+use crate::SourceInfo;
+
+/// Encoding of raw interleaved samples
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PcmFormat {
+    #[default]
+    S16Le,
+    S32Le,
+    F32Le,
+}
+
+impl PcmFormat {
+    pub const fn sample_bytes(&self) -> usize {
+        match self {
+            Self::S16Le => 2,
+            Self::S32Le | Self::F32Le => 4,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f32 {
+        match *self {
+            Self::S16Le => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32_768.0,
+            Self::S32Le => {
+                let sample = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                sample as f32 / 2_147_483_648.0
+            }
+            Self::F32Le => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+impl std::str::FromStr for PcmFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Ok(match input {
+            "s16le" => Self::S16Le,
+            "s32le" => Self::S32Le,
+            "f32le" => Self::F32Le,
+            format => {
+                anyhow::bail!("unknown pcm format {format:?}, expected s16le, s32le or f32le")
+            }
+        })
+    }
+}
+
+/// Raw interleaved PCM read from a pipe, a FIFO, a file or any other reader
+///
+/// Reads block until enough samples arrive, so the writer sets the pace
+pub struct RawPcm {
+    reader: Box<dyn Read + Send>,
+    format: PcmFormat,
+    bytes: Vec<u8>,
+    window: Vec<f32>,
+    finished: bool,
+    info: SourceInfo,
+}
+
+impl RawPcm {
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        format: PcmFormat,
+        sample_rate: u32,
+        channels: u16,
+        sample_size: usize,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(sample_rate > 0, "the sample rate must be positive");
+        anyhow::ensure!(channels > 0, "at least one channel is required");
+
+        Ok(Self {
+            reader: Box::new(reader),
+            format,
+            bytes: Vec::with_capacity(sample_size * format.sample_bytes()),
+            window: Vec::with_capacity(sample_size),
+            finished: false,
+            info: SourceInfo {
+                sample_rate,
+                sample_size,
+                channels,
+            },
+        })
+    }
+
+    pub fn stdin(
+        format: PcmFormat,
+        sample_rate: u32,
+        channels: u16,
+        sample_size: usize,
+    ) -> anyhow::Result<Self> {
+        Self::new(std::io::stdin(), format, sample_rate, channels, sample_size)
+    }
+
+    /// Opens a file or a named pipe, which waits for a writer to show up
+    pub fn open(
+        path: impl AsRef<Path>,
+        format: PcmFormat,
+        sample_rate: u32,
+        channels: u16,
+        sample_size: usize,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file =
+            std::fs::File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+        Self::new(file, format, sample_rate, channels, sample_size)
+    }
+
+    /// The format of this stream, which outlives it being moved into a processing thread
+    pub fn source(&self) -> SourceInfo {
+        self.info
+    }
+}
+
+impl Buffer for RawPcm {
+    #[profiling::function]
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+        if self.finished {
+            return None;
+        }
+
+        // keep the tail of the last window, and only read what's new
+        let keep = sample_size.saturating_sub(hop_size).min(self.window.len());
+        self.window.drain(..self.window.len() - keep);
+
+        let size = self.format.sample_bytes();
+        self.bytes
+            .resize((sample_size - self.window.len()) * size, 0);
+
+        // end of stream or a broken pipe, either way nothing more is coming
+        if self.reader.read_exact(&mut self.bytes).is_err() {
+            self.finished = true;
+            return None;
+        }
+
+        self.window.extend(
+            self.bytes
+                .chunks_exact(size)
+                .map(|bytes| self.format.decode(bytes)),
+        );
+
+        Some(&self.window)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Source for RawPcm {
+    fn sample_rate(&self) -> u32 {
+        self.info.sample_rate
+    }
+
+    fn sample_size(&self) -> usize {
+        self.info.sample_size
+    }
+
+    fn channels(&self) -> u16 {
+        self.info.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn raw(bytes: Vec<u8>, format: PcmFormat) -> RawPcm {
+        RawPcm::new(Cursor::new(bytes), format, 48_000, 2, 4).unwrap()
+    }
+
+    const SAMPLES: [f32; 4] = [0.0, 0.5, -0.5, -1.0];
+
+    #[test]
+    fn decodes_s16le() {
+        let bytes = SAMPLES
+            .iter()
+            .flat_map(|&sample| ((sample * 32_768.0) as i16).to_le_bytes())
+            .collect();
+        assert_eq!(
+            raw(bytes, PcmFormat::S16Le).read_samples(4, 4),
+            Some(&SAMPLES[..])
+        );
+    }
+
+    #[test]
+    fn decodes_s32le() {
+        let bytes = SAMPLES
+            .iter()
+            .flat_map(|&sample| ((sample as f64 * 2_147_483_648.0) as i32).to_le_bytes())
+            .collect();
+        assert_eq!(
+            raw(bytes, PcmFormat::S32Le).read_samples(4, 4),
+            Some(&SAMPLES[..])
+        );
+    }
+
+    #[test]
+    fn decodes_f32le() {
+        let bytes = SAMPLES
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        assert_eq!(
+            raw(bytes, PcmFormat::F32Le).read_samples(4, 4),
+            Some(&SAMPLES[..])
+        );
+    }
+
+    #[test]
+    fn drops_a_trailing_partial_frame() {
+        // two whole windows, and half a frame after them
+        let mut bytes = (0..8_i16).flat_map(i16::to_le_bytes).collect::<Vec<_>>();
+        bytes.extend([0, 1]);
+        let mut pcm = raw(bytes, PcmFormat::S16Le);
+
+        for _ in 0..2 {
+            assert!(pcm.read_samples(4, 4).is_some());
+            assert!(!pcm.is_finished());
+        }
+        assert!(pcm.read_samples(4, 4).is_none());
+        assert!(pcm.is_finished());
+    }
+
+    #[test]
+    fn rejects_zero_channels() {
+        let pcm = RawPcm::new(Cursor::new(Vec::new()), PcmFormat::S16Le, 48_000, 0, 4);
+        assert!(pcm.is_err());
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use parking_lot::Mutex;
+
+use scram_process::{Buffer, Format, Source};
+
+fn wav_spec(source: &dyn Source) -> hound::WavSpec {
+    hound::WavSpec {
+        channels: source.channels(),
+        sample_rate: source.sample_rate(),
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    }
+}
+
+fn rolling_capacity(duration: Duration, spec: hound::WavSpec) -> usize {
+    let frames = (duration.as_secs_f64() * spec.sample_rate as f64).ceil() as usize;
+    frames.max(1) * spec.channels as usize
+}
+
+fn create_wav(
+    path: &Path,
+    spec: hound::WavSpec,
+) -> anyhow::Result<hound::WavWriter<BufWriter<File>>> {
+    hound::WavWriter::create(path, spec)
+        .with_context(|| format!("cannot create {}", path.display()))
+}
+
+/// The most recent samples, kept around until someone asks for them
+struct Rolling {
+    samples: VecDeque<f32>,
+    /// How much audio is kept
+    duration: Duration,
+    capacity: usize,
+    spec: hound::WavSpec,
+}
+
+/// A handle to the samples a rolling [`Recorder`] is holding on to
+#[derive(Clone)]
+pub struct Recording {
+    rolling: Arc<Mutex<Rolling>>,
+}
+
+impl Recording {
+    /// Writes what was recorded so far to a WAV file, and keeps recording
+    pub fn dump(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        // copy out first, so the recorder isn't kept waiting on the disk
+        let (samples, spec) = {
+            let rolling = self.rolling.lock();
+            (Vec::from(rolling.samples.clone()), rolling.spec)
+        };
+
+        let mut writer = create_wav(path, spec)?;
+        for sample in samples {
+            writer.write_sample(sample)?;
+        }
+        writer
+            .finalize()
+            .with_context(|| format!("cannot write {}", path.display()))
+    }
+
+    /// How much audio a dump would hold
+    pub fn duration(&self) -> Duration {
+        let rolling = self.rolling.lock();
+        let frames = rolling.samples.len() / rolling.spec.channels as usize;
+        Duration::from_secs_f64(frames as f64 / rolling.spec.sample_rate as f64)
+    }
+}
+
+enum Sink {
+    File {
+        writer: hound::WavWriter<BufWriter<File>>,
+        path: PathBuf,
+        /// Samples written since the header was last brought up to date
+        unflushed: usize,
+        flush_every: usize,
+    },
+    Rolling(Arc<Mutex<Rolling>>),
+    /// Writing failed, or the source switched formats, so recording stopped
+    Stopped,
+}
+
+/// Records everything another [`Buffer`] streams, and cuts windows out of it
+///
+/// Samples are recorded as they come in, before any are skipped to catch up, so the recording
+/// has no gaps even when analysis falls behind. A WAV file can't switch formats midway, so a
+/// recording to a file stops when the source does, and a rolling recording starts over
+pub struct Recorder<B> {
+    inner: B,
+    sink: Sink,
+    /// Recorded samples that windows are cut from, the next window starts at the front
+    pending: Vec<f32>,
+    primed: bool,
+    /// Interleaved samples the source produces per second
+    samples_per_second: f64,
+    next_due: Instant,
+    errors: flume::Sender<anyhow::Error>,
+    error_rx: flume::Receiver<anyhow::Error>,
+}
+
+impl<B: Buffer> Recorder<B> {
+    fn new(inner: B, sink: Sink, spec: hound::WavSpec) -> Self {
+        let (errors, error_rx) = flume::unbounded();
+        Self {
+            inner,
+            sink,
+            pending: Vec::new(),
+            primed: false,
+            samples_per_second: spec.sample_rate as f64 * spec.channels as f64,
+            next_due: Instant::now(),
+            errors,
+            error_rx,
+        }
+    }
+
+    /// Records everything to a WAV file as it arrives
+    pub fn to_file(inner: B, source: &dyn Source, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let spec = wav_spec(source);
+        let writer = create_wav(path, spec)?;
+
+        let sink = Sink::File {
+            writer,
+            path: path.to_owned(),
+            unflushed: 0,
+            // the header only gets its lengths when flushed, and there may never be a clean exit
+            flush_every: spec.sample_rate as usize * spec.channels as usize,
+        };
+        Ok(Self::new(inner, sink, spec))
+    }
+
+    /// Only holds on to the last `duration` of audio, which is written to disk with [`Recording::dump`]
+    pub fn rolling(inner: B, source: &dyn Source, duration: Duration) -> (Self, Recording) {
+        let spec = wav_spec(source);
+        let capacity = rolling_capacity(duration, spec);
+
+        let rolling = Arc::new(Mutex::new(Rolling {
+            samples: VecDeque::with_capacity(capacity),
+            duration,
+            capacity,
+            spec,
+        }));
+
+        let this = Self::new(inner, Sink::Rolling(rolling.clone()), spec);
+        (this, Recording { rolling })
+    }
+
+    /// Why recording stopped, once it does
+    pub fn errors(&self) -> flume::Receiver<anyhow::Error> {
+        self.error_rx.clone()
+    }
+}
+
+impl Sink {
+    /// Stops recording after the first write that fails
+    fn record(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        match self {
+            Self::File {
+                writer,
+                path,
+                unflushed,
+                flush_every,
+            } => {
+                let mut result = samples
+                    .iter()
+                    .try_for_each(|&sample| writer.write_sample(sample));
+
+                *unflushed += samples.len();
+                if result.is_ok() && *unflushed >= *flush_every {
+                    *unflushed = 0;
+                    result = writer.flush();
+                }
+
+                if let Err(err) = result {
+                    let err = anyhow::Error::new(err)
+                        .context(format!("stopped recording to {}", path.display()));
+                    *self = Self::Stopped;
+                    return Err(err);
+                }
+            }
+            Self::Rolling(rolling) => {
+                let Rolling {
+                    samples: kept,
+                    capacity,
+                    ..
+                } = &mut *rolling.lock();
+
+                let samples = &samples[samples.len().saturating_sub(*capacity)..];
+                let overflow = (kept.len() + samples.len()).saturating_sub(*capacity);
+                kept.drain(..overflow);
+                kept.extend(samples);
+            }
+            Self::Stopped => {}
+        }
+        Ok(())
+    }
+
+    fn switch_format(&mut self, format: Format) -> anyhow::Result<()> {
+        match self {
+            Self::File { path, .. } => {
+                let err = anyhow::anyhow!(
+                    "stopped recording to {}, the source switched to {} Hz and {} channels",
+                    path.display(),
+                    format.sample_rate,
+                    format.channels
+                );
+                *self = Self::Stopped;
+                return Err(err);
+            }
+            Self::Rolling(rolling) => {
+                let rolling = &mut *rolling.lock();
+                rolling.spec = hound::WavSpec {
+                    channels: format.channels,
+                    sample_rate: format.sample_rate,
+                    ..rolling.spec
+                };
+                rolling.capacity = rolling_capacity(rolling.duration, rolling.spec);
+                rolling.samples.clear();
+            }
+            Self::Stopped => {}
+        }
+        Ok(())
+    }
+}
+
+impl<B: Buffer> Buffer for Recorder<B> {
+    #[profiling::function]
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+        let hop_size = hop_size.clamp(1, sample_size.max(1));
+        if std::mem::replace(&mut self.primed, true) {
+            self.pending.drain(..hop_size.min(self.pending.len()));
+        }
+
+        let buffered = self.pending.len() >= sample_size;
+        while self.pending.len() < sample_size {
+            let samples = self.inner.read_stream(sample_size - self.pending.len())?;
+            if let Err(err) = self.sink.record(samples) {
+                _ = self.errors.send(err);
+            }
+            self.pending.extend_from_slice(samples);
+        }
+
+        // when falling more than a window behind, skip whole hops to catch up
+        if self.pending.len() > 2 * sample_size {
+            let backlog = self.pending.len() - sample_size;
+            let skip = (backlog - sample_size).div_ceil(hop_size) * hop_size;
+            let skip = skip.min(backlog);
+            self.pending.drain(..skip);
+            self.inner.report_dropped(skip);
+            self.next_due = Instant::now();
+        }
+
+        // a stream read can hand over several hops at once, so spread the windows cut from
+        // them out over time. Windows that had to wait on the stream are already paced by it
+        let now = Instant::now();
+        if let Some(wait) = self
+            .next_due
+            .checked_duration_since(now)
+            .filter(|_| buffered)
+        {
+            profiling::scope!("pace windows");
+            std::thread::sleep(wait);
+        }
+        let hop_time = Duration::from_secs_f64(hop_size as f64 / self.samples_per_second);
+        self.next_due = self.next_due.max(now) + hop_time;
+
+        Some(&self.pending[..sample_size])
+    }
+
+    #[profiling::function]
+    fn read_stream(&mut self, min_size: usize) -> Option<&[f32]> {
+        let samples = self.inner.read_stream(min_size)?;
+        if let Err(err) = self.sink.record(samples) {
+            _ = self.errors.send(err);
+        }
+        Some(samples)
+    }
+
+    fn report_dropped(&mut self, samples: usize) {
+        self.inner.report_dropped(samples);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    fn next_format(&mut self) -> Option<Format> {
+        let format = self.inner.next_format()?;
+        if let Err(err) = self.sink.switch_format(format) {
+            _ = self.errors.send(err);
+        }
+        self.pending.clear();
+        self.primed = false;
+        self.samples_per_second = format.sample_rate as f64 * format.channels as f64;
+        Some(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SourceInfo;
+
+    /// Every sample is its own index, and the format switches once `switch` is set
+    #[derive(Default)]
+    struct Counting {
+        next: usize,
+        window: Vec<f32>,
+        switch: Option<Format>,
+    }
+
+    impl Buffer for Counting {
+        fn read_samples(&mut self, sample_size: usize, _hop_size: usize) -> Option<&[f32]> {
+            self.window = (self.next..self.next + sample_size)
+                .map(|n| n as f32)
+                .collect();
+            self.next += sample_size;
+            Some(&self.window)
+        }
+
+        fn next_format(&mut self) -> Option<Format> {
+            self.switch.take()
+        }
+    }
+
+    fn source(sample_rate: u32, channels: u16) -> SourceInfo {
+        SourceInfo {
+            sample_rate,
+            sample_size: 64,
+            channels,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("scram-{}-{name}.wav", std::process::id()))
+    }
+
+    fn read_back(path: &Path) -> (hound::WavSpec, Vec<f32>) {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        let samples = reader.samples::<f32>().map(Result::unwrap).collect();
+        let spec = reader.spec();
+        std::fs::remove_file(path).unwrap();
+        (spec, samples)
+    }
+
+    #[test]
+    fn records_every_sample_streamed() {
+        let path = temp_path("file");
+        let mut recorder =
+            Recorder::to_file(Counting::default(), &source(48_000, 2), &path).unwrap();
+        for _ in 0..20 {
+            recorder.read_samples(64, 16).unwrap();
+        }
+        drop(recorder);
+
+        let (spec, samples) = read_back(&path);
+        assert_eq!((spec.sample_rate, spec.channels), (48_000, 2));
+        let recorded = 64 + 19 * 16;
+        assert!(samples.iter().copied().eq((0..recorded).map(|n| n as f32)));
+    }
+
+    #[test]
+    fn rolling_keeps_the_last_of_it() {
+        let path = temp_path("rolling");
+        let duration = Duration::from_millis(100);
+        let (mut recorder, recording) =
+            Recorder::rolling(Counting::default(), &source(1000, 1), duration);
+        for _ in 0..10 {
+            recorder.read_samples(64, 64).unwrap();
+        }
+
+        assert_eq!(recording.duration(), duration);
+        recording.dump(&path).unwrap();
+        let (_, samples) = read_back(&path);
+        assert!(samples.iter().copied().eq((540..640).map(|n| n as f32)));
+    }
+
+    #[test]
+    fn says_why_a_file_stopped() {
+        let path = temp_path("switch");
+        let mut recorder =
+            Recorder::to_file(Counting::default(), &source(48_000, 2), &path).unwrap();
+        let errors = recorder.errors();
+        recorder.read_samples(64, 64).unwrap();
+
+        recorder.inner.switch = Some(Format {
+            sample_rate: 44_100,
+            channels: 1,
+        });
+        assert!(recorder.next_format().is_some());
+        recorder.read_samples(64, 64).unwrap();
+        drop(recorder);
+
+        assert_eq!(errors.try_iter().count(), 1);
+        let (_, samples) = read_back(&path);
+        assert_eq!(samples.len(), 64);
+    }
+
+    /// Hands over a window and three more hops at once, like a device with long callbacks
+    struct Bursty(Counting);
+
+    impl Buffer for Bursty {
+        fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+            self.0.read_samples(sample_size, hop_size)
+        }
+
+        fn read_stream(&mut self, min_size: usize) -> Option<&[f32]> {
+            self.0.read_samples(min_size.max(56), 0)
+        }
+    }
+
+    #[test]
+    fn spreads_windows_out_over_a_burst() {
+        let (mut recorder, _) = Recorder::rolling(
+            Bursty(Counting::default()),
+            &source(1000, 1),
+            Duration::from_secs(1),
+        );
+
+        // the windows come in together, and go out 8 ms apart
+        let start = Instant::now();
+        for hop in 0..4 {
+            let window = recorder.read_samples(32, 8).unwrap();
+            assert_eq!(window[0], (hop * 8) as f32);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(24));
+    }
+}
@@ -0,0 +1,243 @@
+//! A preallocated single-producer, single-consumer queue of samples
+//!
+//! Neither side allocates, locks or blocks, so the producer is safe to use from a real-time audio
+//! callback. Each side only touches its own part of the storage, and hands it over to the other
+//! side by publishing a counter with release/acquire ordering
+
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+struct Shared {
+    samples: Box<[UnsafeCell<f32>]>,
+    /// Total samples ever written, only stored by the producer
+    written: AtomicUsize,
+    /// Total samples ever read, only stored by the consumer
+    read: AtomicUsize,
+}
+
+// SAFETY: the producer only writes to slots that were read, and the consumer only reads slots that
+// were written, which the counters guarantee never overlap
+unsafe impl Sync for Shared {}
+
+impl Shared {
+    fn len(&self) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        let written = self.written.load(Ordering::Acquire);
+        written.wrapping_sub(read).min(self.samples.len())
+    }
+
+    /// The `len` slots starting at `position`, as the two contiguous runs they wrap around into
+    ///
+    /// # Safety
+    /// The slots must belong to the caller's side of the queue, and `len` can't exceed the capacity
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn slots(&self, position: usize, len: usize) -> (&mut [f32], &mut [f32]) {
+        let capacity = self.samples.len();
+        let start = position % capacity;
+        let first = len.min(capacity - start);
+
+        // `UnsafeCell<f32>` has the same layout as `f32`
+        let base = UnsafeCell::raw_get(self.samples.as_ptr());
+        // SAFETY: both runs are in bounds, don't overlap, and are only touched by the caller
+        unsafe {
+            (
+                std::slice::from_raw_parts_mut(base.add(start), first),
+                std::slice::from_raw_parts_mut(base, len - first),
+            )
+        }
+    }
+}
+
+/// Creates a queue that holds up to `capacity` samples
+pub fn channel(capacity: usize) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        samples: (0..capacity.max(1)).map(|_| UnsafeCell::new(0.0)).collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+
+    let producer = Producer {
+        shared: shared.clone(),
+    };
+    (producer, Consumer { shared })
+}
+
+/// The writing half of a [`channel`]
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+impl Producer {
+    /// Writes as many samples as fit, and returns how many did
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        self.push_map(samples, |sample| sample)
+    }
+
+    /// Writes as many samples as fit, converting each one on the way in, and returns how many did
+    pub fn push_map<T: Copy>(&mut self, samples: &[T], convert: impl Fn(T) -> f32) -> usize {
+        let shared = &*self.shared;
+        let capacity = shared.samples.len();
+
+        let written = shared.written.load(Ordering::Relaxed);
+        let free = capacity - written.wrapping_sub(shared.read.load(Ordering::Acquire));
+        let count = samples.len().min(free);
+
+        // SAFETY: free slots belong to the producer until `written` moves past them
+        let (first, second) = unsafe { shared.slots(written, count) };
+        let (head, tail) = samples[..count].split_at(first.len());
+        for (slot, &sample) in first.iter_mut().zip(head) {
+            *slot = convert(sample);
+        }
+        for (slot, &sample) in second.iter_mut().zip(tail) {
+            *slot = convert(sample);
+        }
+
+        shared
+            .written
+            .store(written.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Samples waiting to be read
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.samples.len()
+    }
+}
+
+/// The reading half of a [`channel`]
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+impl Consumer {
+    /// Fills as much of `out` as there are samples for, and returns how much that was
+    pub fn pop(&mut self, out: &mut [f32]) -> usize {
+        let shared = &*self.shared;
+
+        let read = shared.read.load(Ordering::Relaxed);
+        let available = shared.written.load(Ordering::Acquire).wrapping_sub(read);
+        let count = out.len().min(available);
+
+        // SAFETY: written slots belong to the consumer until `read` moves past them
+        let (first, second) = unsafe { shared.slots(read, count) };
+        let (head, tail) = out[..count].split_at_mut(first.len());
+        head.copy_from_slice(first);
+        tail.copy_from_slice(second);
+
+        shared
+            .read
+            .store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Throws away up to `count` samples, and returns how many there were
+    pub fn skip(&mut self, count: usize) -> usize {
+        let shared = &*self.shared;
+        let read = shared.read.load(Ordering::Relaxed);
+        let available = shared.written.load(Ordering::Acquire).wrapping_sub(read);
+
+        let count = count.min(available);
+        shared
+            .read
+            .store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Samples waiting to be read
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.samples.len()
+    }
+
+    /// Whether the producer is gone, so nothing more is coming
+    pub fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.shared) == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let (mut producer, mut consumer) = channel(4);
+        let mut out = [0.0; 3];
+
+        assert_eq!(producer.push(&[1.0, 2.0, 3.0]), 3);
+        assert_eq!(consumer.pop(&mut out), 3);
+        assert_eq!(out, [1.0, 2.0, 3.0]);
+
+        assert_eq!(producer.push(&[4.0, 5.0, 6.0]), 3);
+        assert_eq!(consumer.len(), 3);
+        assert_eq!(consumer.pop(&mut out), 3);
+        assert_eq!(out, [4.0, 5.0, 6.0]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn drops_what_does_not_fit() {
+        let (mut producer, mut consumer) = channel(4);
+        assert_eq!(producer.push(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), 4);
+        assert_eq!(producer.push(&[7.0]), 0);
+
+        assert_eq!(consumer.skip(2), 2);
+        let mut out = [0.0; 4];
+        assert_eq!(consumer.pop(&mut out), 2);
+        assert_eq!(out[..2], [3.0, 4.0]);
+    }
+
+    #[test]
+    fn keeps_order_across_threads() {
+        const COUNT: usize = 100_000;
+        let (mut producer, mut consumer) = channel(64);
+
+        let writer = std::thread::spawn(move || {
+            let samples = (0..COUNT).collect::<Vec<_>>();
+            let mut next = 0;
+            while next < COUNT {
+                let end = (next + 7).min(COUNT);
+                match producer.push_map(&samples[next..end], |i| i as f32) {
+                    0 => std::thread::yield_now(),
+                    pushed => next += pushed,
+                }
+            }
+        });
+
+        let mut expected = 0;
+        let mut out = [0.0; 5];
+        while expected < COUNT {
+            let read = consumer.pop(&mut out);
+            if read == 0 {
+                std::thread::yield_now();
+            }
+            for &sample in &out[..read] {
+                assert_eq!(sample, expected as f32);
+                expected += 1;
+            }
+        }
+
+        writer.join().unwrap();
+        assert!(consumer.is_abandoned());
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Counters bumped from the audio callback and the reader, without locking either
+pub(crate) struct Counters {
+    epoch: Instant,
+    callbacks: AtomicU64,
+    samples_received: AtomicU64,
+    samples_dropped: AtomicU64,
+    queued: AtomicU64,
+    /// Since `epoch`
+    last_callback_nanos: AtomicU64,
+    latency_nanos: AtomicU64,
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            callbacks: AtomicU64::default(),
+            samples_received: AtomicU64::default(),
+            samples_dropped: AtomicU64::default(),
+            queued: AtomicU64::default(),
+            last_callback_nanos: AtomicU64::default(),
+            latency_nanos: AtomicU64::default(),
+        }
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+impl Counters {
+    pub fn received(&self, samples: usize) {
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.samples_received
+            .fetch_add(samples as u64, Ordering::Relaxed);
+        self.last_callback_nanos
+            .store(nanos(self.epoch.elapsed()), Ordering::Relaxed);
+    }
+
+    /// How long ago the device last handed over samples
+    pub fn since_last_callback(&self) -> Duration {
+        let last = Duration::from_nanos(self.last_callback_nanos.load(Ordering::Relaxed));
+        self.epoch.elapsed().saturating_sub(last)
+    }
+
+    pub fn set_queued(&self, samples: usize) {
+        self.queued.store(samples as u64, Ordering::Relaxed);
+    }
+
+    pub fn dropped(&self, samples: usize) {
+        self.samples_dropped
+            .fetch_add(samples as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        self.latency_nanos.store(nanos(latency), Ordering::Relaxed);
+    }
+}
+
+/// How healthy capture is, at some point in time
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Times the device handed over samples
+    pub callbacks: u64,
+    pub samples_received: u64,
+    /// Samples thrown away because the reader fell behind
+    pub samples_dropped: u64,
+    /// Samples waiting to be read
+    pub queue_depth: usize,
+    /// How old the newest samples of the last window were by the time it was read
+    pub latency: Duration,
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "callbacks {} | received {} | dropped {} | queued {} | latency {:.1} ms",
+            self.callbacks,
+            self.samples_received,
+            self.samples_dropped,
+            self.queue_depth,
+            self.latency.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// A handle for reading capture [`Stats`] from any thread
+#[derive(Clone)]
+pub struct CaptureStats {
+    pub(crate) counters: Arc<Counters>,
+}
+
+impl CaptureStats {
+    pub fn snapshot(&self) -> Stats {
+        let counters = &self.counters;
+        Stats {
+            callbacks: counters.callbacks.load(Ordering::Relaxed),
+            samples_received: counters.samples_received.load(Ordering::Relaxed),
+            samples_dropped: counters.samples_dropped.load(Ordering::Relaxed),
+            queue_depth: counters.queued.load(Ordering::Relaxed) as usize,
+            latency: Duration::from_nanos(counters.latency_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
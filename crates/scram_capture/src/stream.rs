@@ -0,0 +1,306 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context as _;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use parking_lot::Mutex;
+
+use crate::{
+    CaptureConfig, CaptureMode, DeviceSelector, SampleFormat, StreamFormat, convert, device, ring,
+    stats::Counters,
+};
+
+/// What the capture thread is up to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    /// Capturing from this device
+    Connected {
+        device: String,
+        format: StreamFormat,
+    },
+    /// The device went away, and capture resumes once it (or a new default device) shows up
+    Reconnecting { reason: String },
+    /// The device reported a problem, but capture carries on
+    Error(String),
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connected { device, format } => write!(
+                f,
+                "capturing from {device} ({} Hz, {} ch, {})",
+                format.sample_rate, format.channels, format.sample_format
+            ),
+            Self::Reconnecting { reason } => write!(f, "{reason}, waiting for a device"),
+            Self::Error(err) => write!(f, "capture error: {err}"),
+        }
+    }
+}
+
+pub(crate) enum Control {
+    /// An error from the stream of the given connection
+    Stream(u64, cpal::StreamError),
+    Shutdown,
+}
+
+struct Connection {
+    _stream: cpal::Stream,
+    id: u64,
+    device: String,
+    format: StreamFormat,
+}
+
+impl Connection {
+    fn status(&self) -> Status {
+        Status::Connected {
+            device: self.device.clone(),
+            format: self.format,
+        }
+    }
+}
+
+/// Where the samples, their format and the errors of a stream go
+#[derive(Clone)]
+struct Sink {
+    /// Only one stream is alive at a time, the lock only hands the producer from one to the next
+    producer: Arc<Mutex<ring::Producer>>,
+    /// Sent before a stream produces anything, so the reader knows everything queued before
+    /// it is in the old format
+    formats: flume::Sender<StreamFormat>,
+    counters: Arc<Counters>,
+    control: flume::Sender<Control>,
+}
+
+/// A running capture thread
+pub(crate) struct Capture {
+    pub control: flume::Sender<Control>,
+    pub status: flume::Receiver<Status>,
+    pub device: String,
+    pub format: StreamFormat,
+}
+
+/// Starts capturing on a thread of its own, which owns the stream since
+/// streams can't be moved between threads on every platform
+pub(crate) fn spawn(
+    capture: CaptureConfig,
+    producer: ring::Producer,
+    formats: flume::Sender<StreamFormat>,
+    counters: Arc<Counters>,
+) -> anyhow::Result<Capture> {
+    let (control_tx, control_rx) = flume::unbounded();
+    let (status_tx, status_rx) = flume::unbounded();
+    let (ready_tx, ready_rx) = flume::bounded(1);
+
+    let sink = Sink {
+        producer: Arc::new(Mutex::new(producer)),
+        formats,
+        counters,
+        control: control_tx.clone(),
+    };
+    std::thread::Builder::new()
+        .name(String::from("scram capture"))
+        .spawn(move || {
+            let host = cpal::default_host();
+            let connection = match connect(&host, &capture.device, capture.mode, 0, &sink) {
+                Ok(connection) => connection,
+                Err(err) => {
+                    _ = ready_tx.send(Err(err));
+                    return;
+                }
+            };
+
+            _ = ready_tx.send(Ok((connection.device.clone(), connection.format)));
+            _ = status_tx.send(connection.status());
+
+            Supervisor {
+                host,
+                capture,
+                sink,
+                status: status_tx,
+                next_id: 1,
+            }
+            .run(connection, control_rx)
+        })?;
+
+    let (device, format) = ready_rx
+        .recv()
+        .with_context(|| "the capture thread went away")??;
+
+    Ok(Capture {
+        control: control_tx,
+        status: status_rx,
+        device,
+        format,
+    })
+}
+
+struct Supervisor {
+    host: cpal::Host,
+    capture: CaptureConfig,
+    sink: Sink,
+    status: flume::Sender<Status>,
+    next_id: u64,
+}
+
+impl Supervisor {
+    const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+    const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+    fn run(mut self, connection: Connection, control: flume::Receiver<Control>) {
+        let mut connection = Some(connection);
+        let mut last_default_check = Instant::now();
+
+        loop {
+            match control.recv_timeout(Self::RETRY_INTERVAL) {
+                Ok(Control::Shutdown) | Err(flume::RecvTimeoutError::Disconnected) => return,
+                Ok(Control::Stream(id, err)) => {
+                    // errors from streams that were already replaced don't matter
+                    if connection.as_ref().is_none_or(|c| c.id != id) {
+                        continue;
+                    }
+                    if let cpal::StreamError::DeviceNotAvailable = err {
+                        connection = None;
+                        self.report(Status::Reconnecting {
+                            reason: String::from("the device went away"),
+                        });
+                    } else {
+                        self.report(Status::Error(err.to_string()));
+                    }
+                }
+                Err(flume::RecvTimeoutError::Timeout) => {}
+            }
+
+            // follow the default device around when that's what was asked for
+            if let Some(current) = &connection {
+                if self.capture.device == DeviceSelector::Default
+                    && last_default_check.elapsed() >= Self::DEFAULT_CHECK_INTERVAL
+                {
+                    last_default_check = Instant::now();
+                    if self
+                        .default_device_name()
+                        .is_some_and(|name| name != current.device)
+                    {
+                        connection = None;
+                        self.report(Status::Reconnecting {
+                            reason: String::from("the default device changed"),
+                        });
+                    }
+                }
+            }
+
+            if connection.is_none() {
+                connection = self.reconnect();
+                if let Some(connection) = &connection {
+                    self.report(connection.status());
+                }
+            }
+        }
+    }
+
+    fn report(&self, status: Status) {
+        _ = self.status.send(status);
+    }
+
+    fn default_device_name(&self) -> Option<String> {
+        let device = match self.capture.mode {
+            CaptureMode::Loopback => self.host.default_output_device(),
+            CaptureMode::Input => self.host.default_input_device(),
+        };
+        device.as_ref().map(device::device_name)
+    }
+
+    /// Tries the original device, then the default one
+    fn reconnect(&mut self) -> Option<Connection> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mode = self.capture.mode;
+        connect(&self.host, &self.capture.device, mode, id, &self.sink)
+            .or_else(|_| connect(&self.host, &DeviceSelector::Default, mode, id, &self.sink))
+            .ok()
+    }
+}
+
+fn connect(
+    host: &cpal::Host,
+    selector: &DeviceSelector,
+    mode: CaptureMode,
+    id: u64,
+    sink: &Sink,
+) -> anyhow::Result<Connection> {
+    let device = device::find_device(host, selector, mode)?;
+    let device_name = device::device_name(&device);
+
+    let supported = device::default_config(&device, mode)?;
+    let config = supported.config();
+    let format = StreamFormat::from(&supported);
+
+    // some platforms start streams as soon as they're built
+    _ = sink.formats.send(format);
+    let sink = sink.clone();
+    let stream = match supported.sample_format() {
+        SampleFormat::I8 => build_stream::<i8>(&device, &config, id, sink),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, id, sink),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, id, sink),
+        SampleFormat::I64 => build_stream::<i64>(&device, &config, id, sink),
+        SampleFormat::U8 => build_stream::<u8>(&device, &config, id, sink),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, id, sink),
+        SampleFormat::U32 => build_stream::<u32>(&device, &config, id, sink),
+        SampleFormat::U64 => build_stream::<u64>(&device, &config, id, sink),
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, id, sink),
+        SampleFormat::F64 => build_stream::<f64>(&device, &config, id, sink),
+        format => anyhow::bail!("unsupported sample format: {format}"),
+    }?;
+
+    stream
+        .play()
+        .with_context(|| format!("cannot start the stream for {device_name:?}"))?;
+
+    Ok(Connection {
+        _stream: stream,
+        id,
+        device: device_name,
+        format,
+    })
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    id: u64,
+    sink: Sink,
+) -> anyhow::Result<cpal::Stream>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let Sink {
+        producer,
+        counters,
+        control,
+        ..
+    } = sink;
+
+    let stream = device.build_input_stream(
+        config,
+        move |samples: &[T], _| {
+            counters.received(samples.len());
+
+            // never block the audio thread, samples are lost instead when the previous stream
+            // hasn't let go of the producer yet, or when the reader fell behind
+            let Some(mut producer) = producer.try_lock() else {
+                counters.dropped(samples.len());
+                return;
+            };
+            let written = producer.push_map(samples, convert::to_f32);
+            counters.dropped(samples.len() - written);
+            counters.set_queued(producer.len());
+        },
+        move |err| _ = control.send(Control::Stream(id, err)),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,293 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Context as _;
+
+use scram_process::{Buffer, Source};
+
+use crate::{Pacing, SourceInfo, pacing::Pacer};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WavOptions {
+    pub pacing: Pacing,
+    /// Start over from the beginning when the end of the file is reached
+    pub looping: bool,
+}
+
+/// A PCM or float WAV file, decoded up front, read as if it were a device
+pub struct WavFile {
+    samples: Box<[f32]>,
+    window: Vec<f32>,
+    /// Start of the most recent window
+    position: usize,
+    primed: bool,
+    /// Length of the last stream read, which the next one starts after
+    streamed: usize,
+    finished: bool,
+    looping: bool,
+    pacer: Pacer,
+    info: SourceInfo,
+}
+
+impl WavFile {
+    pub fn open(
+        path: impl AsRef<Path>,
+        sample_size: usize,
+        options: WavOptions,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+        Self::from_reader(BufReader::new(file), sample_size, options)
+            .with_context(|| format!("cannot read {}", path.display()))
+    }
+
+    /// Decodes a whole WAV file from `reader`, which doesn't have to be a file on disk
+    pub fn from_reader(
+        reader: impl Read,
+        sample_size: usize,
+        options: WavOptions,
+    ) -> anyhow::Result<Self> {
+        let mut reader = hound::WavReader::new(reader)?;
+
+        let spec = reader.spec();
+        let samples: hound::Result<Vec<f32>> = match (spec.sample_format, spec.bits_per_sample) {
+            (hound::SampleFormat::Float, 32) => reader.samples::<f32>().collect(),
+            (hound::SampleFormat::Int, bits @ 1..=32) => {
+                let scale = ((1_u64 << (bits - 1)) as f32).recip();
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect()
+            }
+            (format, bits) => anyhow::bail!("unsupported wav format: {bits}-bit {format:?}"),
+        };
+        let mut samples = samples.context("cannot decode the samples")?;
+
+        // drop a trailing partial frame
+        let channels = spec.channels as usize;
+        samples.truncate(samples.len() - samples.len() % channels);
+        anyhow::ensure!(!samples.is_empty(), "there are no samples");
+
+        Ok(Self {
+            samples: samples.into_boxed_slice(),
+            window: Vec::with_capacity(sample_size),
+            position: 0,
+            primed: false,
+            streamed: 0,
+            finished: false,
+            looping: options.looping,
+            pacer: Pacer::new(options.pacing, spec.sample_rate),
+            info: SourceInfo {
+                sample_rate: spec.sample_rate,
+                sample_size,
+                channels: spec.channels,
+            },
+        })
+    }
+
+    /// The format of this file, which outlives the file being moved into a processing thread
+    pub fn source(&self) -> SourceInfo {
+        self.info
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.to_duration(self.samples.len())
+    }
+
+    pub fn position(&self) -> Duration {
+        self.to_duration(self.position)
+    }
+
+    /// Moves the read position, wrapping around when looping and stopping at the end otherwise
+    pub fn seek(&mut self, position: Duration) {
+        let channels = self.info.channels as usize;
+        let frame = (position.as_secs_f64() * self.info.sample_rate as f64) as usize;
+
+        let mut position = frame.saturating_mul(channels);
+        if self.looping {
+            position %= self.samples.len();
+        }
+
+        self.position = position.min(self.samples.len());
+        self.primed = false;
+        self.finished = false;
+        self.pacer.reset();
+    }
+
+    fn to_duration(&self, samples: usize) -> Duration {
+        let frames = samples / self.info.channels as usize;
+        Duration::from_secs_f64(frames as f64 / self.info.sample_rate as f64)
+    }
+}
+
+impl Buffer for WavFile {
+    #[profiling::function]
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+        if self.finished {
+            return None;
+        }
+
+        let len = self.samples.len();
+        let advance = match std::mem::replace(&mut self.primed, true) {
+            true => hop_size,
+            false => 0,
+        };
+
+        self.position += advance;
+        if self.position >= len {
+            if !self.looping {
+                self.finished = true;
+                return None;
+            }
+            self.position %= len;
+        }
+
+        let fresh = if advance == 0 { sample_size } else { advance };
+        self.pacer.wait(fresh / self.info.channels as usize);
+
+        let end = self.position + sample_size;
+        if end <= len {
+            return Some(&self.samples[self.position..end]);
+        }
+
+        // the window runs past the end, so stitch it together
+        self.window.clear();
+        let mut position = self.position;
+        while self.window.len() < sample_size {
+            let wanted = sample_size - self.window.len();
+            let available = &self.samples[position..];
+            let take = wanted.min(available.len());
+            self.window.extend_from_slice(&available[..take]);
+            position += take;
+
+            if position == len {
+                if !self.looping {
+                    self.window.resize(sample_size, 0.0);
+                    break;
+                }
+                position = 0;
+            }
+        }
+
+        Some(&self.window)
+    }
+
+    fn read_stream(&mut self, min_size: usize) -> Option<&[f32]> {
+        let advance = std::mem::replace(&mut self.streamed, min_size);
+        self.read_samples(min_size, advance)
+    }
+
+    /// Whether the end of the file was reached, which never happens when looping
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Source for WavFile {
+    fn sample_rate(&self) -> u32 {
+        self.info.sample_rate
+    }
+
+    fn sample_size(&self) -> usize {
+        self.info.sample_size
+    }
+
+    fn channels(&self) -> u16 {
+        self.info.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A mono file at 1 kHz where every sample is its own index
+    fn counting(frames: usize, looping: bool) -> WavFile {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 1000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut bytes = Vec::new();
+        let mut writer = hound::WavWriter::new(Cursor::new(&mut bytes), spec).unwrap();
+        for n in 0..frames {
+            writer.write_sample(n as f32).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let options = WavOptions {
+            pacing: Pacing::Unpaced,
+            looping,
+        };
+        WavFile::from_reader(Cursor::new(bytes), 30, options).unwrap()
+    }
+
+    fn indices(window: &[f32]) -> Vec<usize> {
+        window.iter().map(|&sample| sample as usize).collect()
+    }
+
+    #[test]
+    fn seeks_to_a_frame() {
+        let mut file = counting(100, false);
+        assert_eq!(file.duration(), Duration::from_millis(100));
+
+        file.read_samples(30, 30).unwrap();
+        file.seek(Duration::from_millis(50));
+        assert_eq!(file.position(), Duration::from_millis(50));
+        // the first window after a seek starts right there
+        let window = file.read_samples(30, 30).unwrap();
+        assert_eq!(indices(window), (50..80).collect::<Vec<_>>());
+
+        file.seek(Duration::from_secs(1));
+        assert_eq!(file.position(), file.duration());
+        assert!(file.read_samples(30, 30).is_none());
+    }
+
+    #[test]
+    fn wraps_around_when_looping() {
+        let mut file = counting(100, true);
+        file.seek(Duration::from_millis(150));
+        assert_eq!(file.position(), Duration::from_millis(50));
+
+        file.seek(Duration::ZERO);
+        for _ in 0..3 {
+            file.read_samples(30, 30).unwrap();
+        }
+        let window = file.read_samples(30, 30).unwrap();
+        let expected = (90..100).chain(0..20).collect::<Vec<_>>();
+        assert_eq!(indices(window), expected);
+
+        for _ in 0..100 {
+            assert!(file.read_samples(30, 30).is_some());
+        }
+        assert!(!file.is_finished());
+    }
+
+    #[test]
+    fn finishes_at_the_end() {
+        let mut file = counting(100, false);
+        for start in [0, 30, 60] {
+            let window = file.read_samples(30, 30).unwrap();
+            assert_eq!(indices(window), (start..start + 30).collect::<Vec<_>>());
+            assert!(!file.is_finished());
+        }
+
+        // the last window is filled out with silence
+        let window = file.read_samples(30, 30).unwrap();
+        assert_eq!(
+            &window[..10],
+            &(90..100).map(|n| n as f32).collect::<Vec<_>>()
+        );
+        assert!(window[10..].iter().all(|&sample| sample == 0.0));
+
+        assert!(file.read_samples(30, 30).is_none());
+        assert!(file.is_finished());
+    }
+}
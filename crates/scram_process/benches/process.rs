@@ -0,0 +1,38 @@
+//! What one window costs, from preprocessing through the fft to the smoothed bands
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use scram_process::{Processor, config::Config};
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: u16 = 2;
+const SAMPLE_SIZE: usize = 2048;
+
+/// Bands across a small, a wide and a very wide terminal
+const BANDS: [usize; 3] = [64, 256, 1024];
+
+fn process_samples(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_samples");
+
+    let samples = (0..SAMPLE_SIZE * CHANNELS as usize)
+        .map(|i| (i as f32 * 0.05).sin() * 0.5)
+        .collect::<Vec<_>>();
+
+    for bands in BANDS {
+        let mut processor =
+            Processor::new(SAMPLE_RATE, CHANNELS, SAMPLE_SIZE, Config::default()).unwrap();
+        processor.set_bands(bands);
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(bands),
+            &samples,
+            |b, samples| b.iter(|| processor.process_samples(black_box(samples))),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, process_samples);
+criterion_main!(benches);
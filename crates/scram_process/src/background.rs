@@ -2,17 +2,30 @@ use std::sync::Arc;
 
 use parking_lot::Mutex;
 
-use crate::Frequency;
+use crate::{Band, Frequency};
+
+#[derive(Default)]
+struct Shared {
+    frequencies: Option<[Vec<Frequency>; 2]>,
+    bands: Arc<[Band]>,
+}
 
 #[derive(Clone, Default)]
-pub struct Slot(Arc<Mutex<Option<[Vec<Frequency>; 2]>>>);
+pub struct Slot(Arc<Mutex<Shared>>);
 
 impl Slot {
     pub fn take(&self) -> Option<[Vec<Frequency>; 2]> {
-        self.0.try_lock()?.clone()
+        self.0.try_lock()?.frequencies.clone()
+    }
+
+    /// Where each band of the frequencies last put sits in the spectrum
+    pub fn bands(&self) -> Arc<[Band]> {
+        self.0.lock().bands.clone()
     }
 
-    pub fn put(&self, frequencies: [Vec<Frequency>; 2]) {
-        _ = self.0.lock().replace(frequencies);
+    pub fn put(&self, frequencies: [Vec<Frequency>; 2], bands: Arc<[Band]>) {
+        let mut shared = self.0.lock();
+        shared.frequencies = Some(frequencies);
+        shared.bands = bands;
     }
 }
@@ -1,155 +1,244 @@
-use super::{Banding, Channel, FrequencyScale};
+use super::{Banding, FrequencyScale};
 
-#[profiling::function]
-pub fn aggregate_bands(channel: &mut Channel, sample_rate: u32, banding: &Banding) {
-    channel.band_magnitudes.fill(0.0);
-
-    let opts = Opts {
-        num_bands: channel.band_magnitudes.len(),
-        min_freq: banding.frequency_cutoff.low,
-        max_freq: banding.frequency_cutoff.high,
-        hz_per: (sample_rate as f32 / 2.0) / (channel.fft_magnitudes.len() as f32 - 1.0),
-    };
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
 
-    match banding.scale {
-        FrequencyScale::Linear => apply_linear(channel, opts),
-        FrequencyScale::Logarithmic => apply_logarithmic(channel, opts),
-        FrequencyScale::Bark => apply_bark(channel, opts),
-        FrequencyScale::Mel => apply_mel(channel, opts),
-    }
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10.0_f32.powf(mel / 2595.0) - 1.0)
 }
 
-struct Opts {
-    num_bands: usize,
-    min_freq: f32,
-    max_freq: f32,
-    hz_per: f32,
+fn hz_to_bark(hz: f32) -> f32 {
+    7.0 * ((hz / 600.0) + ((hz / 600.0).powi(2) + 1.0).sqrt()).ln()
 }
 
-#[profiling::function]
-fn apply_linear(channel: &mut Channel, opts: Opts) {
-    let total = channel.fft_magnitudes.len();
-    let ratio = (opts.max_freq - opts.min_freq) / opts.num_bands as f32;
+fn bark_to_hz(bark: f32) -> f32 {
+    600.0 * (bark / 7.0).sinh()
+}
 
-    for sample in 0..opts.num_bands {
-        // let start_hz = (opts.min_freq + sample as f32 * opts.hz_per) / opts.hz_per;
-        // let end_hz = (opts.min_freq + (sample + 1) as f32 * opts.hz_per) / opts.hz_per;
+/// Glasberg and Moore's ERB-rate, in ERBs below `hz`
+fn hz_to_erb(hz: f32) -> f32 {
+    21.4 * (1.0 + 0.00437 * hz).log10()
+}
 
-        let start_hz = opts.min_freq + sample as f32 * ratio;
-        let end_hz = opts.min_freq + (sample + 1) as f32 * ratio;
+fn erb_to_hz(erb: f32) -> f32 {
+    (10.0_f32.powf(erb / 21.4) - 1.0) / 0.00437
+}
 
-        let start_hz = start_hz / opts.hz_per;
-        let end_hz = end_hz / opts.hz_per;
+/// The base-10 octave of IEC 61260, a hair wider than a doubling
+const OCTAVE_RATIO: f32 = 1.995_262_3;
+const REFERENCE_HZ: f32 = 1000.0;
 
-        let start = (start_hz.floor() as usize).clamp(0, total);
-        let end = (end_hz.ceil() as usize).min(total);
+/// Where a band starts, peaks and ends, in Hz
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Band {
+    pub low: f32,
+    pub center: f32,
+    pub high: f32,
+}
 
-        let count = end.saturating_sub(start);
-        let mag = channel.fft_magnitudes[start..end].iter().sum::<f32>();
+/// Mel, bark and ERB bands are overlapping triangles, the others are rectangles side by side
+pub fn is_triangular(scale: &FrequencyScale) -> bool {
+    matches!(
+        scale,
+        FrequencyScale::Mel | FrequencyScale::Bark | FrequencyScale::Erb
+    )
+}
 
-        let norm = if count > 0 { mag / total as f32 } else { 0.0 };
-        channel.band_magnitudes[sample] = norm;
+/// How many bands there are, given that `requested` were asked for
+pub fn count(requested: usize, banding: &Banding) -> usize {
+    match banding.scale {
+        FrequencyScale::Octave { fraction } if banding.fixed_bands => {
+            octave_bands(fraction, banding).len()
+        }
+        FrequencyScale::Custom(ref edges) => edges.len().saturating_sub(1),
+        _ => requested,
     }
 }
 
-#[profiling::function]
-fn apply_logarithmic(channel: &mut Channel, opts: Opts) {
-    let total = channel.fft_magnitudes.len();
-
-    let base = (opts.max_freq / opts.min_freq).powf(1.0 / opts.num_bands as f32);
-    for sample in 0..opts.num_bands - 1 {
-        let start_hz = opts.min_freq * base.powf(sample as f32);
-        let end_hz = opts.min_freq * base.powf((sample + 1) as f32);
-
-        let start_freq = start_hz / opts.hz_per;
-        let end_freq = end_hz / opts.hz_per;
-
-        let start = (start_freq.floor() as usize).clamp(0, total);
-        let end = (end_freq.ceil() as usize).min(total);
-
-        let count = end.saturating_sub(start);
-        let mag = if count > 0 {
-            channel.fft_magnitudes[start..end].iter().sum::<f32>()
-        } else {
-            0.0
-        };
-
-        let norm = if count > 0 { mag / total as f32 } else { 0.0 };
-        channel.band_magnitudes[sample] = norm;
-    }
+/// The ISO 266 preferred numbers that octave and third-octave bands are named after
+const NOMINAL: [f32; 11] = [1.0, 1.25, 1.6, 2.0, 2.5, 3.15, 4.0, 5.0, 6.3, 8.0, 10.0];
+
+/// The mid-band frequency a band is known by, like 2 kHz for the exact 1995 Hz. Octaves and
+/// thirds use the preferred numbers, finer fractions are rounded to three significant figures
+fn nominal(exact: f32, fraction: u8) -> f32 {
+    let decade = 10.0_f32.powf(exact.log10().floor());
+    let mantissa = exact / decade;
+    let rounded = match fraction {
+        1 | 3 => NOMINAL
+            .into_iter()
+            .min_by(|a, b| {
+                (mantissa / a)
+                    .ln()
+                    .abs()
+                    .total_cmp(&(mantissa / b).ln().abs())
+            })
+            .unwrap_or(mantissa),
+        _ => (mantissa * 100.0).round() / 100.0,
+    };
+    rounded * decade
+}
 
-    let sample = opts.num_bands.saturating_sub(1);
-    let start_hz = opts.min_freq * base.powf(sample as f32);
-    let end_hz = opts.max_freq;
+/// The fractional-octave bands from the one nearest the low cutoff to the one nearest the
+/// high cutoff, with exact edges and nominal mid-band frequencies
+fn octave_bands(fraction: u8, banding: &Banding) -> Vec<Band> {
+    let fraction = fraction.max(1);
+    // odd fractions have a band centered on the reference, even ones have an edge there
+    let offset = match fraction % 2 {
+        1 => 0.0,
+        _ => 0.5,
+    };
 
-    let start_freq = start_hz / opts.hz_per;
-    let end_freq = end_hz / opts.hz_per;
-    let start = (start_freq.floor() as usize).clamp(0, total);
-    let end = (end_freq.ceil() as usize).min(total);
+    let fraction = fraction as f32;
+    let cutoff = &banding.frequency_cutoff;
+    let index = |hz: f32| fraction * (hz.max(1.0) / REFERENCE_HZ).log(OCTAVE_RATIO) - offset;
+    let first = index(cutoff.low).round() as i32;
+    let last = index(cutoff.high).round() as i32;
+
+    let half = OCTAVE_RATIO.powf(0.5 / fraction);
+    (first..=last)
+        .map(|m| {
+            let exact = REFERENCE_HZ * OCTAVE_RATIO.powf((m as f32 + offset) / fraction);
+            Band {
+                low: exact / half,
+                center: nominal(exact, fraction as u8),
+                high: exact * half,
+            }
+        })
+        .collect()
+}
 
-    let count = end.saturating_sub(start);
-    let mag = if count > 0 {
-        channel.fft_magnitudes[start..end].iter().sum::<f32>()
-    } else {
-        0.0
+/// Splits the cutoff range into `bands` bands, evenly spaced on the scale
+pub fn layout(bands: usize, banding: &Banding) -> Vec<Band> {
+    let [to_scale, from_scale]: [fn(f32) -> f32; 2] = match banding.scale {
+        FrequencyScale::Linear => [|hz| hz, |hz| hz],
+        FrequencyScale::Logarithmic => [f32::ln, f32::exp],
+        FrequencyScale::Mel => [hz_to_mel, mel_to_hz],
+        FrequencyScale::Bark => [hz_to_bark, bark_to_hz],
+        FrequencyScale::Erb => [hz_to_erb, erb_to_hz],
+        FrequencyScale::Octave { fraction } => {
+            let natural = octave_bands(fraction, banding);
+            return spread(&natural, bands)
+                .into_iter()
+                .map(|band| natural[band])
+                .collect();
+        }
+        FrequencyScale::Custom(ref edges) => return custom_bands(edges),
     };
 
-    let norm = if count > 0 { mag / total as f32 } else { 0.0 };
-    channel.band_magnitudes[sample] = norm;
+    let cutoff = &banding.frequency_cutoff;
+    let (low, high) = (to_scale(cutoff.low), to_scale(cutoff.high));
+
+    // triangles reach out to the centers of their neighbours
+    let triangular = is_triangular(&banding.scale);
+    let steps = bands + triangular as usize;
+    let step = (high - low) / steps.max(1) as f32;
+    let at = |i: f32| from_scale(low + i * step);
+
+    (0..bands)
+        .map(|band| {
+            let band = band as f32;
+            match triangular {
+                true => Band {
+                    low: at(band),
+                    center: at(band + 1.0),
+                    high: at(band + 2.0),
+                },
+                false => Band {
+                    low: at(band),
+                    center: at(band + 0.5),
+                    high: at(band + 1.0),
+                },
+            }
+        })
+        .collect()
 }
 
-#[profiling::function]
-fn apply_mel(channel: &mut Channel, opts: Opts) {
-    fn hz_to_mel(hz: f32) -> f32 {
-        2595.0 * (1.0 + hz / 700.0).log10()
-    }
-    fn mel_to_hz(mel: f32) -> f32 {
-        700.0 * (10.0_f32.powf(mel / 2595.0) - 1.0)
+/// Which of a fixed set of `natural` bands each of `bands` bands shows, repeating each over
+/// the bands that fall within it on a log scale, like the bars of an analyzer
+pub fn spread(natural: &[Band], bands: usize) -> Vec<usize> {
+    let (Some(first), Some(last)) = (natural.first(), natural.last()) else {
+        return Vec::new();
+    };
+    if natural.len() == bands {
+        return (0..bands).collect();
     }
 
-    let total = channel.fft_magnitudes.len();
+    let (low, high) = (first.low.ln(), last.high.ln());
+    let step = (high - low) / bands.max(1) as f32;
 
-    let min_mel = hz_to_mel(opts.min_freq);
-    let max_mel = hz_to_mel(opts.max_freq);
-    let mel_per = (max_mel - min_mel) / opts.num_bands as f32;
-
-    for sample in 0..opts.num_bands {
-        let start_hz = mel_to_hz(min_mel + sample as f32 * mel_per) / opts.hz_per;
-        let end_hz = mel_to_hz(min_mel + (sample + 1) as f32 * mel_per) / opts.hz_per;
-
-        let start = (start_hz.floor() as usize).clamp(0, total);
-        let end = (end_hz.ceil() as usize).min(total);
+    (0..bands)
+        .map(|band| {
+            let hz = (low + (band as f32 + 0.5) * step).exp();
+            let index = natural.partition_point(|natural| natural.high < hz);
+            index.min(natural.len() - 1)
+        })
+        .collect()
+}
 
-        let count = end.saturating_sub(start);
-        let mag = channel.fft_magnitudes[start..end].iter().sum::<f32>();
-        let norm = if count > 0 { 1.0 } else { 0.0 };
-        channel.band_magnitudes[sample] = mag / total as f32 * norm;
-    }
+fn custom_bands(edges: &[f32]) -> Vec<Band> {
+    edges
+        .windows(2)
+        .map(|pair| {
+            let (low, high) = (pair[0], pair[1]);
+            let center = match low > 0.0 {
+                true => (low * high).sqrt(),
+                false => high / 2.0,
+            };
+            Band { low, center, high }
+        })
+        .collect()
 }
 
-#[profiling::function]
-fn apply_bark(channel: &mut Channel, opts: Opts) {
-    fn hz_to_bark(hz: f32) -> f32 {
-        7.0 * ((hz / 600.0) + ((hz / 600.0).powi(2) + 1.0).sqrt()).ln()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FilterNormalization, FrequencyCutoff};
+
+    fn octaves(fraction: u8) -> Vec<Band> {
+        let banding = Banding {
+            frequency_cutoff: FrequencyCutoff {
+                low: 20.0,
+                high: 20_000.0,
+            },
+            scale: FrequencyScale::Octave { fraction },
+            normalization: FilterNormalization::Peak,
+            fixed_bands: true,
+        };
+        octave_bands(fraction, &banding)
     }
-    fn bark_to_hz(bark: f32) -> f32 {
-        600.0 * (bark / 7.0).sinh()
+
+    #[test]
+    fn octaves_are_named_by_their_nominal_frequencies() {
+        let centers = octaves(1)
+            .iter()
+            .map(|band| band.center)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            centers,
+            [
+                16.0, 31.5, 63.0, 125.0, 250.0, 500.0, 1_000.0, 2_000.0, 4_000.0, 8_000.0,
+                16_000.0,
+            ]
+        );
+
+        let thirds = octaves(3);
+        let centers = thirds.iter().map(|band| band.center);
+        let expected = [
+            20.0, 25.0, 31.5, 40.0, 50.0, 63.0, 80.0, 100.0, 125.0, 160.0,
+        ];
+        assert!(centers.take(10).eq(expected));
     }
 
-    let total = channel.fft_magnitudes.len();
-    let min_bark = hz_to_bark(opts.min_freq);
-    let max_bark = hz_to_bark(opts.max_freq);
-    let bark_per = (max_bark - min_bark) / opts.num_bands as f32;
-
-    for sample in 0..opts.num_bands {
-        let start_hz = bark_to_hz(min_bark + sample as f32 * bark_per) / opts.hz_per;
-        let end_hz = bark_to_hz(min_bark + (sample + 1) as f32 * bark_per) / opts.hz_per;
-        let start = (start_hz.floor() as usize).clamp(0, total);
-        let end = (end_hz.ceil() as usize).min(total);
-
-        let count = end.saturating_sub(start);
-        let mag = channel.fft_magnitudes[start..end].iter().sum::<f32>();
-        let norm = if count > 0 { 1.0 } else { 0.0 };
-        channel.band_magnitudes[sample] = mag / total as f32 * norm;
+    #[test]
+    fn octave_edges_stay_exact() {
+        let bands = octaves(1);
+        for pair in bands.windows(2) {
+            assert!((pair[0].high / pair[1].low - 1.0).abs() < 1e-6);
+        }
+
+        let at_2k = bands.iter().find(|band| band.center == 2_000.0).unwrap();
+        let exact = (at_2k.low * at_2k.high).sqrt();
+        assert!((exact - 1_995.262).abs() < 0.01, "{exact}");
     }
 }
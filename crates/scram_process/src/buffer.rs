@@ -1,14 +1,64 @@
+/// The layout of the samples a [`Buffer`] hands out
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Format {
+    pub sample_rate: u32,
+    /// Number of interleaved channels
+    pub channels: u16,
+}
+
 pub trait Buffer: Send + 'static {
-    fn read_samples(&mut self, sample_size: usize) -> Option<&[f32]>;
+    /// Reads the next window of `sample_size` interleaved samples
+    ///
+    /// Each window starts `hop_size` samples after the one before it, so
+    /// consecutive windows overlap when `hop_size` is less than `sample_size`
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]>;
+    /// Reads every sample that came in since the last read, at least `min_size` of them,
+    /// without skipping any to catch up. For readers that cut their own windows out of the
+    /// stream, and that [report](Buffer::report_dropped) what they skip instead
+    fn read_stream(&mut self, min_size: usize) -> Option<&[f32]> {
+        self.read_samples(min_size, min_size)
+    }
+    /// Counts samples that a reader of the [stream](Buffer::read_stream) threw away
+    fn report_dropped(&mut self, samples: usize) {
+        _ = samples;
+    }
+    /// Whether this buffer will never produce samples again
+    fn is_finished(&self) -> bool {
+        false
+    }
+    /// The format the samples switched to, if they did since this was last asked. A read that
+    /// would mix the old format with the new one returns `None` instead, so this is asked
+    /// before every read, and every read after it is all in the new format
+    fn next_format(&mut self) -> Option<Format> {
+        None
+    }
 }
 
-impl<T: Buffer> Buffer for Box<T> {
-    fn read_samples(&mut self, sample_size: usize) -> Option<&[f32]> {
-        (**self).read_samples(sample_size)
+impl<T: Buffer + ?Sized> Buffer for Box<T> {
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+        (**self).read_samples(sample_size, hop_size)
+    }
+
+    fn read_stream(&mut self, min_size: usize) -> Option<&[f32]> {
+        (**self).read_stream(min_size)
+    }
+
+    fn report_dropped(&mut self, samples: usize) {
+        (**self).report_dropped(samples)
+    }
+
+    fn is_finished(&self) -> bool {
+        (**self).is_finished()
+    }
+
+    fn next_format(&mut self) -> Option<Format> {
+        (**self).next_format()
     }
 }
 
 pub trait Source {
     fn sample_rate(&self) -> u32;
     fn sample_size(&self) -> usize;
+    /// Number of interleaved channels in the samples
+    fn channels(&self) -> u16;
 }
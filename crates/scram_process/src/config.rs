@@ -1,27 +1,192 @@
-#[derive(Copy, Clone, Default, Debug, PartialEq)]
+use std::sync::Arc;
+
+#[derive(Clone, Default, Debug, PartialEq)]
 pub struct Config {
+    pub analysis: Analysis,
     pub banding: Banding,
     pub window: Window,
+    /// How each frequency is weighted before it's gathered into bands
+    pub weighting: Weighting,
+    pub tilt: Tilt,
+    /// How many times longer than the window the fft is, with the rest filled with silence.
+    /// Doesn't add resolution, but spreads the spectrum over more bins so narrow bands have
+    /// something to land on. `0` and `1` both mean no padding
+    pub zero_padding: usize,
+    pub hop: Hop,
+    pub channel_map: ChannelMap,
     pub scaling: VolumeScale,
     pub band_smoothing: BandSmoothing,
     pub peak_smoothing: PeakSmoothing,
+    pub silence: Silence,
 }
 
-#[derive(Copy, Clone, Default, Debug, PartialEq)]
+#[derive(Clone, Default, Debug, PartialEq)]
 pub struct Banding {
     pub frequency_cutoff: FrequencyCutoff,
     pub scale: FrequencyScale,
+    pub normalization: FilterNormalization,
+    /// Use the bands the scale itself has between the cutoffs, however many bands were asked
+    /// for. Only fractional-octave scales have a set of their own, custom scales always use theirs
+    pub fixed_bands: bool,
 }
 
-#[derive(Copy, Clone, Default, Debug, PartialEq)]
+#[derive(Clone, Default, Debug, PartialEq)]
 pub enum FrequencyScale {
     Linear,
     Logarithmic,
     Bark,
     #[default]
     Mel,
+    /// Equivalent rectangular bandwidths, which follow the ear's auditory filters
+    Erb,
+    /// Base-10 fractional octaves (IEC 61260), centered on the standard frequencies around 1 kHz.
+    /// `fraction` is 1 for whole octaves, 3 for thirds and so on
+    Octave {
+        fraction: u8,
+    },
+    /// Bands side by side between these edges, in Hz, however many bands were asked for.
+    /// The cutoffs don't apply
+    Custom(Arc<[f32]>),
+}
+
+impl Config {
+    /// Checks for settings that can't work at `sample_rate` with `channels` interleaved channels
+    pub fn validate(&self, sample_rate: u32, channels: u16) -> anyhow::Result<()> {
+        if let ChannelMap::Pair { left, right } = self.channel_map {
+            let highest = left.max(right);
+            anyhow::ensure!(
+                highest < channels as usize,
+                "channel {highest} doesn't exist, there are only {channels} (counting from 0)"
+            );
+        }
+
+        if let FrequencyScale::Custom(edges) = &self.banding.scale {
+            let nyquist = sample_rate as f32 / 2.0;
+            anyhow::ensure!(edges.len() >= 2, "custom bands need at least two edges");
+            anyhow::ensure!(
+                edges.iter().all(|edge| (0.0..=nyquist).contains(edge)),
+                "custom band edges have to be within 0..={nyquist} Hz at {sample_rate} Hz"
+            );
+            anyhow::ensure!(
+                edges.windows(2).all(|pair| pair[0] < pair[1]),
+                "custom band edges have to be increasing"
+            );
+        }
+
+        if let Analysis::ConstantQ {
+            bins_per_octave,
+            min_frequency,
+        } = self.analysis
+        {
+            let nyquist = sample_rate as f32 / 2.0;
+            anyhow::ensure!(
+                bins_per_octave > 0,
+                "constant-Q needs at least one bin per octave"
+            );
+            anyhow::ensure!(
+                min_frequency > 0.0 && min_frequency < nyquist,
+                "the constant-Q minimum frequency has to be within 0..{nyquist} Hz at {sample_rate} Hz"
+            );
+            anyhow::ensure!(
+                min_frequency < self.banding.frequency_cutoff.high,
+                "the constant-Q minimum frequency has to be below the high cutoff"
+            );
+        }
+
+        match self.window {
+            Window::Kaiser { beta } => {
+                anyhow::ensure!(beta >= 0.0, "the kaiser window's beta can't be negative");
+            }
+            Window::Gaussian { sigma } => {
+                anyhow::ensure!(sigma > 0.0, "the gaussian window's sigma has to be above 0");
+            }
+            Window::Tukey { alpha } => {
+                anyhow::ensure!(
+                    (0.0..=1.0).contains(&alpha),
+                    "the tukey window's alpha has to be within 0..=1"
+                );
+            }
+            _ => {}
+        }
+
+        anyhow::ensure!(self.tilt.pivot > 0.0, "the tilt pivot has to be above 0 Hz");
+        anyhow::ensure!(
+            self.silence.hold >= 0.0,
+            "the silence hold time can't be negative"
+        );
+        Ok(())
+    }
+}
+
+/// How each window is turned into bands
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub enum Analysis {
+    /// An fft, with the bins gathered into bands by the [`Banding`] scale
+    #[default]
+    Fft,
+    /// A constant-Q transform, where every bin spans the same musical interval and low notes
+    /// are as easy to tell apart as high ones. Bins go from `min_frequency` up to the high cutoff,
+    /// and are spread over the bands asked for unless [`Banding::fixed_bands`] is set. The scale
+    /// and window don't apply
+    ///
+    /// Kernels are capped at 16384 samples to keep the transform cheap, so bins below about
+    /// `q * sample_rate / 16384` Hz, where `q` is `1 / (2^(1 / bins_per_octave) - 1)`, are wider
+    /// than their interval. At 48 kHz that's 49 Hz with 12 bins per octave and 100 Hz with 24
+    ConstantQ {
+        bins_per_octave: usize,
+        min_frequency: f32,
+    },
+}
+
+/// How the triangular filters of the mel, bark and ERB scales are weighted
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub enum FilterNormalization {
+    /// Every filter peaks at one, so wider bands collect more energy
+    #[default]
+    Peak,
+    /// Every filter has the same area, so broadband noise comes out flat
+    Area,
+}
+
+/// Standard frequency weightings, so levels follow how loud things sound rather than how much
+/// energy they carry. Each passes 1 kHz unchanged, apart from K, which is about +0.7 dB there
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub enum Weighting {
+    #[default]
+    None,
+    /// IEC 61672 A-weighting, as most sound level meters use
+    A,
+    /// IEC 61672 C-weighting, flatter than A for loud sounds
+    C,
+    /// ITU-R BS.1770 K-weighting, as loudness (LUFS) meters use
+    K,
+    /// ITU-R 468 noise weighting, which peaks around 6.3 kHz
+    Itu468,
+}
+
+/// A slope applied across the bands, by their center frequencies, to level out spectra that
+/// change with frequency. Pink noise rises around 3 dB per octave in bands that sum their bins,
+/// like rectangles and triangles with [`FilterNormalization::Peak`], so a slope of -3 levels it
+/// out. Triangles with [`FilterNormalization::Area`] average their bins instead, where it falls
+/// by as much and a slope of 3 does
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tilt {
+    /// Gain added per octave above the pivot, and taken away per octave below it
+    pub slope: f32,
+    /// The frequency, in Hz, that's left as it is
+    pub pivot: f32,
+}
+
+impl Default for Tilt {
+    fn default() -> Self {
+        Self {
+            slope: 0.0,
+            pivot: 1000.0,
+        }
+    }
 }
 
+/// What each window is weighted by before the fft, trading frequency resolution for leakage
 #[derive(Copy, Clone, Default, Debug, PartialEq)]
 pub enum Window {
     None,
@@ -29,6 +194,63 @@ pub enum Window {
     Hamming,
     #[default]
     Blackman,
+    BlackmanHarris,
+    Nuttall,
+    /// Reads amplitudes right wherever a sine falls between bins, but smears it over several
+    FlatTop,
+    /// Higher `beta` leaks less but widens the main lobe, 8.6 is close to Blackman-Harris
+    Kaiser {
+        beta: f32,
+    },
+    /// Flat in the middle, with the outer `alpha` (`0.0..=1.0`) of the window tapered.
+    /// 0 is no window at all and 1 is Hann
+    Tukey {
+        alpha: f32,
+    },
+    /// A gaussian curve with a standard deviation of `sigma` half-windows
+    Gaussian {
+        sigma: f32,
+    },
+}
+
+/// How far apart consecutive analysis windows start
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Hop {
+    /// A fixed number of frames
+    Frames(usize),
+    /// The fraction (`0.0..1.0`) of each window shared with the next one
+    Overlap(f32),
+}
+
+impl Hop {
+    /// The hop, in frames, for a window of `window` frames
+    pub fn frames(&self, window: usize) -> usize {
+        let frames = match *self {
+            Self::Frames(frames) => frames,
+            Self::Overlap(overlap) => {
+                let overlap = overlap.clamp(0.0, 0.99);
+                (window as f32 * (1.0 - overlap)).round() as usize
+            }
+        };
+        frames.clamp(1, window.max(1))
+    }
+}
+
+impl Default for Hop {
+    fn default() -> Self {
+        Self::Overlap(0.5)
+    }
+}
+
+/// How the interleaved input channels are mapped onto the left and right channels
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub enum ChannelMap {
+    /// Mono is duplicated onto both sides, stereo is passed through and
+    /// surround layouts (in SMPTE order) are folded down into left and right
+    #[default]
+    Downmix,
+    /// Use these (zero-based) input channels as left and right
+    Pair { left: usize, right: usize },
 }
 
 #[derive(Copy, Clone, Default, Debug, PartialEq)]
@@ -72,6 +294,38 @@ impl Default for BandSmoothing {
     }
 }
 
+/// When the input counts as silent, and what the processor does about it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Silence {
+    /// Level, in dBFS, that the RMS of each window has to stay under
+    pub threshold: f32,
+    /// Seconds of audio that have to stay under the threshold before the input is silent
+    pub hold: f32,
+    pub behavior: SilenceBehavior,
+}
+
+impl Default for Silence {
+    fn default() -> Self {
+        Self {
+            threshold: -60.0,
+            hold: 2.0,
+            behavior: SilenceBehavior::Publish,
+        }
+    }
+}
+
+/// What happens to the frequencies once the input is silent
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
+pub enum SilenceBehavior {
+    /// Keep analysing and publishing as usual
+    #[default]
+    Publish,
+    /// Stop analysing, so nothing new is published until there's sound again
+    Stop,
+    /// Let every bar fall to zero, then stop
+    Decay,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct FrequencyCutoff {
     pub low: f32,
@@ -86,3 +340,228 @@ impl Default for FrequencyCutoff {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Buffer, Format, Processor};
+
+    fn custom(edges: &[f32]) -> Config {
+        Config {
+            banding: Banding {
+                scale: FrequencyScale::Custom(edges.into()),
+                ..Banding::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    fn constant_q(bins_per_octave: usize, min_frequency: f32) -> Config {
+        Config {
+            analysis: Analysis::ConstantQ {
+                bins_per_octave,
+                min_frequency,
+            },
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        Config::default().validate(48_000, 2).unwrap();
+        custom(&[20.0, 200.0, 2_000.0, 20_000.0])
+            .validate(48_000, 2)
+            .unwrap();
+        constant_q(24, 32.7).validate(48_000, 2).unwrap();
+    }
+
+    #[test]
+    fn rejects_edges_that_dont_increase() {
+        assert!(custom(&[20.0, 200.0, 200.0]).validate(48_000, 2).is_err());
+        assert!(custom(&[20.0, 2_000.0, 200.0]).validate(48_000, 2).is_err());
+    }
+
+    #[test]
+    fn rejects_edges_above_nyquist() {
+        let config = custom(&[20.0, 200.0, 20_000.0]);
+        config.validate(48_000, 2).unwrap();
+        assert!(config.validate(32_000, 2).is_err());
+        assert!(custom(&[-20.0, 200.0]).validate(48_000, 2).is_err());
+    }
+
+    #[test]
+    fn rejects_fewer_than_two_edges() {
+        assert!(custom(&[]).validate(48_000, 2).is_err());
+        assert!(custom(&[200.0]).validate(48_000, 2).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_constant_q_settings() {
+        assert!(constant_q(0, 32.7).validate(48_000, 2).is_err());
+        assert!(constant_q(24, 0.0).validate(48_000, 2).is_err());
+        assert!(constant_q(24, 24_000.0).validate(48_000, 2).is_err());
+        // below nyquist, but at the high cutoff there's no room for a single bin
+        assert!(constant_q(24, 18_000.0).validate(48_000, 2).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_window_parameters() {
+        let with = |window| Config {
+            window,
+            ..Config::default()
+        };
+
+        with(Window::Kaiser { beta: 0.0 })
+            .validate(48_000, 2)
+            .unwrap();
+        assert!(
+            with(Window::Kaiser { beta: -1.0 })
+                .validate(48_000, 2)
+                .is_err()
+        );
+
+        with(Window::Gaussian { sigma: 0.4 })
+            .validate(48_000, 2)
+            .unwrap();
+        assert!(
+            with(Window::Gaussian { sigma: 0.0 })
+                .validate(48_000, 2)
+                .is_err()
+        );
+        assert!(
+            with(Window::Gaussian { sigma: -0.4 })
+                .validate(48_000, 2)
+                .is_err()
+        );
+
+        for alpha in [0.0, 0.5, 1.0] {
+            with(Window::Tukey { alpha }).validate(48_000, 2).unwrap();
+        }
+        for alpha in [-0.1, 1.1, f32::NAN] {
+            assert!(with(Window::Tukey { alpha }).validate(48_000, 2).is_err());
+        }
+    }
+
+    #[test]
+    fn processor_keeps_a_valid_config() {
+        let config = custom(&[20.0, 200.0, 20_000.0]);
+        let mut processor = Processor::new(48_000, 2, 1024, config.clone()).unwrap();
+
+        assert!(processor.set_format(32_000, 2).is_err());
+        processor.set_format(44_100, 1).unwrap();
+
+        assert!(processor.set_config(custom(&[200.0, 20.0])).is_err());
+        assert_eq!(processor.config(), &config);
+        processor.set_config(Config::default()).unwrap();
+    }
+
+    #[test]
+    fn switching_formats_falls_back_to_a_config_that_works() {
+        let pair = Config {
+            channel_map: ChannelMap::Pair { left: 0, right: 1 },
+            ..custom(&[20.0, 200.0, 20_000.0])
+        };
+        let mut processor = Processor::new(48_000, 2, 1024, pair.clone()).unwrap();
+
+        processor.switch_format(48_000, 2).unwrap();
+        assert_eq!(processor.config(), &pair);
+
+        // a mono device can't have a second channel
+        assert!(processor.switch_format(48_000, 1).is_err());
+        let downmix = Config {
+            channel_map: ChannelMap::Downmix,
+            ..pair.clone()
+        };
+        assert_eq!(processor.config(), &downmix);
+        assert!(processor.process_samples(&[0.0; 1024]));
+
+        // and the edges don't fit under 16 kHz at all
+        assert!(processor.switch_format(32_000, 1).is_err());
+        assert_eq!(processor.config(), &Config::default());
+        assert!(processor.process_samples(&[0.0; 1024]));
+    }
+
+    /// Silent windows, in mono until `switch_at` of them were read and in stereo after
+    struct Switching {
+        read: usize,
+        switch_at: usize,
+        channels: u16,
+        window: Vec<f32>,
+    }
+
+    impl Buffer for Switching {
+        fn read_samples(&mut self, sample_size: usize, _hop_size: usize) -> Option<&[f32]> {
+            assert_eq!(sample_size % self.channels as usize, 0);
+            assert_eq!(sample_size / self.channels as usize, 1024);
+            self.read += 1;
+            self.window = vec![0.0; sample_size];
+            Some(&self.window)
+        }
+
+        fn next_format(&mut self) -> Option<Format> {
+            (self.read == self.switch_at).then(|| {
+                self.channels = 2;
+                Format {
+                    sample_rate: 48_000,
+                    channels: 2,
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn processor_switches_formats_with_the_buffer() {
+        let mut processor = Processor::new(48_000, 1, 1024, Config::default()).unwrap();
+        let mut buffer = Switching {
+            read: 0,
+            switch_at: 3,
+            channels: 1,
+            window: Vec::new(),
+        };
+        for _ in 0..6 {
+            assert!(processor.update(&mut buffer));
+        }
+        assert_eq!(processor.read_size(), 2048);
+        assert!(processor.take_error().is_none());
+    }
+
+    #[test]
+    fn band_count_follows_the_bands_rate_and_banding() {
+        let config = constant_q(12, 1000.0);
+        let mut processor = Processor::new(48_000, 2, 1024, config.clone()).unwrap();
+
+        processor.set_bands(8);
+        assert_eq!(processor.band_count(), 8);
+        processor.set_bands(24);
+        assert_eq!(processor.band_count(), 24);
+
+        // a bin a semitone from 1 kHz up to the 18 kHz cutoff
+        let mut fixed = config;
+        fixed.banding.fixed_bands = true;
+        processor.set_config(fixed).unwrap();
+        assert_eq!(processor.band_count(), 51);
+
+        // and only up to 8 kHz at 16 kHz
+        processor.switch_format(16_000, 2).unwrap();
+        assert_eq!(processor.band_count(), 37);
+        assert!(processor.process_samples(&[0.0; 2048]));
+        assert_eq!(processor.current_frequencies()[0].len(), 37);
+        assert_eq!(processor.bands().len(), 37);
+    }
+
+    #[test]
+    fn changes_in_place_are_checked_before_the_next_window() {
+        let mut processor = Processor::new(48_000, 2, 1024, Config::default()).unwrap();
+
+        processor.config_mut().tilt.slope = -3.0;
+        assert!(processor.process_samples(&[0.0; 2048]));
+        assert_eq!(processor.config().tilt.slope, -3.0);
+        assert!(processor.take_error().is_none());
+
+        processor.config_mut().channel_map = ChannelMap::Pair { left: 0, right: 2 };
+        assert!(processor.process_samples(&[0.0; 2048]));
+        assert_eq!(processor.config().channel_map, ChannelMap::Downmix);
+        assert_eq!(processor.config().tilt.slope, -3.0);
+        assert!(processor.take_error().is_some());
+    }
+}
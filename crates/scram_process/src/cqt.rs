@@ -0,0 +1,261 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use super::{Band, Banding, Channel, Weighting, bands, weighting};
+
+/// Samples the longest kernel may cover. Every bin runs its whole kernel on every window, so
+/// this caps the cost of the low bins, at the price of their resolution
+const MAX_KERNEL: usize = 1 << 14;
+
+/// The bins of a constant-Q transform, from `min_frequency` up to the high cutoff or nyquist
+pub fn bins(
+    bins_per_octave: usize,
+    min_frequency: f32,
+    sample_rate: u32,
+    banding: &Banding,
+) -> Vec<Band> {
+    let bins_per_octave = bins_per_octave.max(1) as f32;
+    let high = banding.frequency_cutoff.high.min(sample_rate as f32 / 2.0);
+    if min_frequency <= 0.0 || high <= min_frequency {
+        return Vec::new();
+    }
+
+    let count = (bins_per_octave * (high / min_frequency).log2()).floor() as usize + 1;
+    let half = 2.0_f32.powf(0.5 / bins_per_octave);
+    (0..count)
+        .map(|bin| {
+            let center = min_frequency * 2.0_f32.powf(bin as f32 / bins_per_octave);
+            Band {
+                low: center / half,
+                center,
+                high: center * half,
+            }
+        })
+        .collect()
+}
+
+/// How many bands there are, given that `requested` were asked for
+pub fn count(requested: usize, bins: usize, banding: &Banding) -> usize {
+    match banding.fixed_bands {
+        true => bins,
+        false => requested,
+    }
+}
+
+/// A windowed complex sinusoid, as long as it takes to tell the bin from its neighbours
+struct Kernel {
+    cos: Box<[f32]>,
+    sin: Box<[f32]>,
+}
+
+impl Kernel {
+    /// `weighting` is the gain of the frequency weighting at the bin
+    fn new(frequency: f32, q: f32, sample_rate: u32, weighting: f32) -> Self {
+        let len = ((q * sample_rate as f32 / frequency).round() as usize).clamp(1, MAX_KERNEL);
+        let hann = |n: usize| 0.5 - 0.5 * (TAU * (n as f32 + 0.5) / len as f32).cos();
+
+        // a full-scale sine at the bin's frequency comes out as one, before weighting
+        let gain = weighting * 2.0 / (0..len).map(hann).sum::<f32>();
+        let omega = TAU * frequency / sample_rate as f32;
+
+        let (cos, sin): (Vec<_>, Vec<_>) = (0..len)
+            .map(|n| {
+                let weight = hann(n) * gain;
+                let phase = omega * n as f32;
+                (weight * phase.cos(), weight * phase.sin())
+            })
+            .unzip();
+        Self {
+            cos: cos.into(),
+            sin: sin.into(),
+        }
+    }
+
+    /// How strongly the end of `history` matches this bin
+    fn apply(&self, history: &[f32]) -> f32 {
+        let samples = &history[history.len().saturating_sub(self.cos.len())..];
+        let offset = self.cos.len() - samples.len();
+
+        let (mut re, mut im) = (0.0, 0.0);
+        for ((sample, cos), sin) in samples
+            .iter()
+            .zip(&self.cos[offset..])
+            .zip(&self.sin[offset..])
+        {
+            re += sample * cos;
+            im += sample * sin;
+        }
+        re.hypot(im)
+    }
+}
+
+/// Everything the kernels were built from, to tell when they have to be built again
+struct Layout {
+    bands: usize,
+    bins_per_octave: usize,
+    min_frequency: f32,
+    sample_rate: u32,
+    banding: Banding,
+    weighting: Weighting,
+}
+
+/// A constant-Q transform, where every bin spans the same musical interval
+///
+/// Low bins need far more samples than one window holds, so each channel keeps a history
+/// of past windows that the kernels run over
+#[derive(Default)]
+pub struct ConstantQ {
+    kernels: Vec<Kernel>,
+    /// Which bin each band shows
+    band_bins: Vec<usize>,
+    magnitudes: Vec<f32>,
+    bands: Arc<[Band]>,
+    layout: Option<Layout>,
+}
+
+impl ConstantQ {
+    /// Builds the kernels again if anything they depend on changed
+    pub fn update(
+        &mut self,
+        bands: usize,
+        bins_per_octave: usize,
+        min_frequency: f32,
+        sample_rate: u32,
+        banding: &Banding,
+        weighting: Weighting,
+    ) {
+        let unchanged = self.layout.as_ref().is_some_and(|layout| {
+            (layout.bands, layout.bins_per_octave, layout.sample_rate)
+                == (bands, bins_per_octave, sample_rate)
+                && layout.min_frequency == min_frequency
+                && layout.banding == *banding
+                && layout.weighting == weighting
+        });
+        if unchanged {
+            return;
+        }
+
+        let layout = Layout {
+            bands,
+            bins_per_octave,
+            min_frequency,
+            sample_rate,
+            banding: banding.clone(),
+            weighting,
+        };
+        self.build(&layout);
+        self.layout = Some(layout);
+    }
+
+    #[profiling::function]
+    fn build(&mut self, layout: &Layout) {
+        let bins = bins(
+            layout.bins_per_octave,
+            layout.min_frequency,
+            layout.sample_rate,
+            &layout.banding,
+        );
+
+        let q = (2.0_f32.powf(1.0 / layout.bins_per_octave.max(1) as f32) - 1.0).recip();
+        self.kernels = bins
+            .iter()
+            .map(|bin| {
+                let weighting = weighting::gain(layout.weighting, bin.center, layout.sample_rate);
+                Kernel::new(bin.center, q, layout.sample_rate, weighting)
+            })
+            .collect();
+        self.magnitudes = vec![0.0; bins.len()];
+
+        self.band_bins = bands::spread(&bins, layout.bands);
+        self.bands = self.band_bins.iter().map(|&bin| bins[bin]).collect();
+    }
+
+    pub fn bands(&self) -> &Arc<[Band]> {
+        &self.bands
+    }
+
+    /// Adds the last `fresh` frames of the window to the channel's history, and transforms it
+    #[profiling::function]
+    pub fn apply(&mut self, channel: &mut Channel, window: usize, fresh: usize) {
+        let longest = self.kernels.first().map_or(0, |kernel| kernel.cos.len());
+        let history = &mut channel.history;
+
+        // the very first window is all new
+        let fresh = match history.is_empty() {
+            true => window,
+            false => fresh.min(window),
+        };
+        history.extend_from_slice(&channel.fft_input[window - fresh..window]);
+        history.drain(..history.len().saturating_sub(longest));
+
+        for (magnitude, kernel) in self.magnitudes.iter_mut().zip(&self.kernels) {
+            *magnitude = kernel.apply(history);
+        }
+        for (band, &bin) in channel.band_magnitudes.iter_mut().zip(&self.band_bins) {
+            *band = self.magnitudes[bin];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    fn fixed() -> Banding {
+        Banding {
+            fixed_bands: true,
+            ..Banding::default()
+        }
+    }
+
+    /// The transform of a window of a sine at `hz`, with a band for every semitone from 110 Hz
+    fn semitones(hz: f32) -> Vec<f32> {
+        let banding = fixed();
+        let bins = bins(12, 110.0, SAMPLE_RATE, &banding).len();
+        let mut cqt = ConstantQ::default();
+        cqt.update(bins, 12, 110.0, SAMPLE_RATE, &banding, Weighting::None);
+
+        let window = MAX_KERNEL;
+        let mut channel = Channel::empty(window);
+        channel.band_magnitudes.resize(bins, 0.0);
+        for (n, sample) in channel.fft_input.iter_mut().enumerate() {
+            *sample = (TAU * hz * n as f32 / SAMPLE_RATE as f32).sin();
+        }
+        cqt.apply(&mut channel, window, window);
+        channel.band_magnitudes
+    }
+
+    #[test]
+    fn a_tone_lands_in_its_semitone() {
+        // A4, two octaves up from the first bin
+        let magnitudes = semitones(440.0);
+        let peak = (0..magnitudes.len())
+            .max_by(|&a, &b| magnitudes[a].total_cmp(&magnitudes[b]))
+            .unwrap();
+        assert_eq!(peak, 24);
+        assert!((magnitudes[24] - 1.0).abs() < 0.05, "{}", magnitudes[24]);
+
+        // the kernels' hann windows reach halfway into the semitones on either side, but no further
+        assert!(magnitudes[23] < 0.6 && magnitudes[25] < 0.6);
+        assert!(magnitudes[22] < 0.05 && magnitudes[26] < 0.05);
+    }
+
+    #[test]
+    fn there_is_a_band_for_every_bin() {
+        let banding = fixed();
+        let bins = bins(24, 32.7, SAMPLE_RATE, &banding);
+        let bands = count(10, bins.len(), &banding);
+        assert_eq!(bands, bins.len());
+
+        let mut cqt = ConstantQ::default();
+        cqt.update(bands, 24, 32.7, SAMPLE_RATE, &banding, Weighting::None);
+        assert_eq!(&cqt.bands()[..], &bins[..]);
+
+        // without fixed bands, the bins are spread over the bands asked for
+        let banding = Banding::default();
+        let bands = count(10, bins.len(), &banding);
+        cqt.update(bands, 24, 32.7, SAMPLE_RATE, &banding, Weighting::None);
+        assert_eq!(cqt.bands().len(), 10);
+    }
+}
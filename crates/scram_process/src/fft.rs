@@ -0,0 +1,224 @@
+//! Backends for the real-to-complex FFT the [`Processor`](crate::Processor) runs on every window
+//!
+//! `microfft` is small and allocation-free, but only does power-of-two sizes from
+//! [`Microfft::MIN_LEN`] to [`Microfft::MAX_LEN`]. `realfft` handles any size, at the cost of a heavier dependency
+
+#[cfg(not(any(feature = "microfft", feature = "realfft")))]
+compile_error!("scram_process needs at least one of the `microfft` or `realfft` features");
+
+/// Turns a window of real samples into a magnitude spectrum
+pub trait Fft: Send {
+    /// Samples per window this was planned for
+    fn size(&self) -> usize;
+
+    /// Transforms `input`, which may be used as scratch space, and writes `size() / 2 + 1`
+    /// magnitudes, from DC up to and including Nyquist
+    fn magnitudes(&mut self, input: &mut [f32], magnitudes: &mut [f32]);
+}
+
+/// Whether any enabled backend can transform windows of `len` samples
+pub fn supports(len: usize) -> bool {
+    #[cfg(feature = "realfft")]
+    if len >= 2 {
+        return true;
+    }
+    #[cfg(feature = "microfft")]
+    if Microfft::supports(len) {
+        return true;
+    }
+    false
+}
+
+/// The closest size at or above `len` that an enabled backend can transform
+pub fn fit(len: usize) -> usize {
+    match supports(len) {
+        true => len,
+        false => len.next_power_of_two(),
+    }
+}
+
+/// Plans a transform of `len` samples with the lightest backend that can do it
+pub fn plan(len: usize) -> anyhow::Result<Box<dyn Fft>> {
+    #[cfg(feature = "microfft")]
+    if Microfft::supports(len) {
+        return Ok(Box::new(Microfft { len }));
+    }
+    #[cfg(feature = "realfft")]
+    if len >= 2 {
+        return Ok(Box::new(Realfft::new(len)));
+    }
+    anyhow::bail!("no fft backend can transform {len} samples")
+}
+
+#[cfg(feature = "microfft")]
+pub struct Microfft {
+    len: usize,
+}
+
+#[cfg(feature = "microfft")]
+impl Microfft {
+    pub const MIN_LEN: usize = 32;
+    pub const MAX_LEN: usize = 32768;
+
+    pub fn supports(len: usize) -> bool {
+        len.is_power_of_two() && (Self::MIN_LEN..=Self::MAX_LEN).contains(&len)
+    }
+
+    pub fn new(len: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            Self::supports(len),
+            "microfft needs a power of two from {} to {}, not {len}",
+            Self::MIN_LEN,
+            Self::MAX_LEN
+        );
+        Ok(Self { len })
+    }
+}
+
+#[cfg(feature = "microfft")]
+impl Fft for Microfft {
+    fn size(&self) -> usize {
+        self.len
+    }
+
+    #[profiling::function]
+    fn magnitudes(&mut self, input: &mut [f32], magnitudes: &mut [f32]) {
+        macro_rules! rfft {
+            ($($size:expr => $func:ident)*) => {
+                match input.len() {
+                    $($size => {
+                        let Ok(data) = <_>::try_from(&mut *input) else {
+                            unreachable!()
+                        };
+                        &mut microfft::real::$func(data)[..]
+                    })*
+                    len => unreachable!("planned for {} samples, got {len}", self.len),
+                }
+            };
+        }
+
+        let spectrum = rfft! {
+            32 => rfft_32
+            64 => rfft_64
+            128 => rfft_128
+            256 => rfft_256
+            512 => rfft_512
+            1024 => rfft_1024
+            2048 => rfft_2048
+            4096 => rfft_4096
+            8192 => rfft_8192
+            16384 => rfft_16384
+            32768 => rfft_32768
+        };
+
+        // DC and Nyquist are both real, so the Nyquist term is packed into the imaginary part of DC
+        let half = spectrum.len();
+        magnitudes[0] = spectrum[0].re.abs();
+        magnitudes[half] = spectrum[0].im.abs();
+        for (magnitude, bin) in magnitudes[1..half].iter_mut().zip(&spectrum[1..]) {
+            *magnitude = bin.re.hypot(bin.im);
+        }
+    }
+}
+
+#[cfg(feature = "realfft")]
+pub struct Realfft {
+    plan: std::sync::Arc<dyn realfft::RealToComplex<f32>>,
+    spectrum: Vec<realfft::num_complex::Complex<f32>>,
+    scratch: Vec<realfft::num_complex::Complex<f32>>,
+}
+
+#[cfg(feature = "realfft")]
+impl Realfft {
+    pub fn new(len: usize) -> Self {
+        let plan = realfft::RealFftPlanner::<f32>::new().plan_fft_forward(len);
+        Self {
+            spectrum: plan.make_output_vec(),
+            scratch: plan.make_scratch_vec(),
+            plan,
+        }
+    }
+}
+
+#[cfg(feature = "realfft")]
+impl Fft for Realfft {
+    fn size(&self) -> usize {
+        self.plan.len()
+    }
+
+    #[profiling::function]
+    fn magnitudes(&mut self, input: &mut [f32], magnitudes: &mut [f32]) {
+        let result = self
+            .plan
+            .process_with_scratch(input, &mut self.spectrum, &mut self.scratch);
+        debug_assert!(result.is_ok(), "{result:?}");
+
+        for (magnitude, bin) in magnitudes.iter_mut().zip(&self.spectrum) {
+            *magnitude = bin.norm();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    /// A sine that finishes `cycles` whole cycles over `len` samples, so it lands in bin `cycles`
+    fn sine(len: usize, cycles: f32) -> Vec<f32> {
+        (0..len)
+            .map(|n| (TAU * cycles * n as f32 / len as f32).sin())
+            .collect()
+    }
+
+    fn magnitudes(fft: &mut dyn Fft, mut input: Vec<f32>) -> Vec<f32> {
+        let mut magnitudes = vec![0.0; fft.size() / 2 + 1];
+        fft.magnitudes(&mut input, &mut magnitudes);
+        magnitudes
+    }
+
+    #[cfg(all(feature = "microfft", feature = "realfft"))]
+    #[test]
+    fn backends_agree() {
+        let len = 1024;
+        // an offset for DC, and a sample-to-sample flip for Nyquist, which microfft packs together
+        let input = sine(len, 37.0)
+            .into_iter()
+            .enumerate()
+            .map(|(n, sample)| sample + 0.25 + if n % 2 == 0 { 0.5 } else { -0.5 })
+            .collect::<Vec<_>>();
+
+        let micro = magnitudes(&mut Microfft::new(len).unwrap(), input.clone());
+        let real = magnitudes(&mut Realfft::new(len), input);
+        // both round differently, on sums of up to `len` samples
+        let tolerance = 1e-4 * len as f32;
+        assert_eq!(micro.len(), real.len());
+        for (bin, (micro, real)) in micro.iter().zip(&real).enumerate() {
+            assert!(
+                (micro - real).abs() < tolerance,
+                "bin {bin}: {micro} and {real}"
+            );
+        }
+
+        assert!((micro[0] - 0.25 * len as f32).abs() < tolerance);
+        assert!((micro[37] - 0.5 * len as f32).abs() < tolerance);
+        assert!((micro[len / 2] - 0.5 * len as f32).abs() < tolerance);
+    }
+
+    #[cfg(feature = "realfft")]
+    #[test]
+    fn any_size_puts_a_sine_in_its_bin() {
+        let len = 1000;
+        assert!(supports(len));
+        let mut fft = plan(len).unwrap();
+        assert_eq!(fft.size(), len);
+
+        let magnitudes = magnitudes(&mut *fft, sine(len, 37.0));
+        let peak = (0..magnitudes.len())
+            .max_by(|&a, &b| magnitudes[a].total_cmp(&magnitudes[b]))
+            .unwrap();
+        assert_eq!(peak, 37);
+        assert!((magnitudes[37] - len as f32 / 2.0).abs() < 1e-4 * len as f32);
+    }
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use super::{
+    Banding, Channel, FilterNormalization,
+    bands::{self, Band},
+};
+
+/// Weights for a run of consecutive bins, starting at `first`
+struct Filter {
+    first: usize,
+    weights: Box<[f32]>,
+}
+
+impl Filter {
+    /// Everything from `low` to `high`, in (fractional) bins
+    ///
+    /// The spectrum is taken to be linear between bins, so bands narrower than a bin get a
+    /// share of it, instead of nothing or the whole bin
+    fn rectangle(low: f32, high: f32, bins: usize) -> Self {
+        let last = bins.saturating_sub(1) as f32;
+        let (low, high) = (low.clamp(0.0, last), high.clamp(0.0, last));
+        let first = (low as usize).min(bins.saturating_sub(2));
+        let end = (high.ceil() as usize + 1).max(first + 2);
+        let mut weights = vec![0.0; end - first].into_boxed_slice();
+
+        let mut from = low;
+        while from < high {
+            let bin = (from as usize).min(end - 2);
+            let to = (bin as f32 + 1.0).min(high);
+            let (a, b) = (from - bin as f32, to - bin as f32);
+
+            // the area under the line from this bin to the next, between `a` and `b`
+            weights[bin - first] += (b - a) * (2.0 - a - b) * 0.5;
+            weights[bin + 1 - first] += (b - a) * (a + b) * 0.5;
+            from = to;
+        }
+
+        Self { first, weights }
+    }
+
+    /// A triangle from `low` up to `center` and back down to `high`, all in (fractional) bins
+    ///
+    /// Each weight is how much of the triangle a bin covers, when the spectrum is taken to be
+    /// linear between bins, so filters narrower than a bin still pick up a share of it
+    fn triangle(low: f32, center: f32, high: f32, bins: usize) -> Self {
+        let last = bins.saturating_sub(1) as f32;
+        let first = (low.floor().max(0.0) as usize).min(bins.saturating_sub(2));
+        let end = (high.ceil().min(last) as usize + 1).max(first + 2);
+        let mut weights = vec![0.0; end - first].into_boxed_slice();
+
+        let width = (high - low).max(f32::EPSILON);
+        let steps = (width * 8.0).ceil().max(16.0) as usize;
+        let dx = width / steps as f32;
+
+        for step in 0..steps {
+            let x = low + (step as f32 + 0.5) * dx;
+            let height = match x < center {
+                true => (x - low) / (center - low).max(f32::EPSILON),
+                false => (high - x) / (high - center).max(f32::EPSILON),
+            };
+
+            // above nyquist, there's nothing to pick up
+            if x > last {
+                break;
+            }
+
+            let x = x.clamp(first as f32, (end - 1) as f32);
+            let bin = (x as usize).min(end - 2);
+            let fraction = x - bin as f32;
+            weights[bin - first] += height * dx * (1.0 - fraction);
+            weights[bin + 1 - first] += height * dx * fraction;
+        }
+
+        Self { first, weights }
+    }
+
+    fn apply(&self, magnitudes: &[f32]) -> f32 {
+        magnitudes[self.first..]
+            .iter()
+            .zip(&self.weights)
+            .map(|(magnitude, weight)| magnitude * weight)
+            .sum()
+    }
+}
+
+/// Everything the filters were built from, to tell when they have to be built again
+struct Layout {
+    bands: usize,
+    bins: usize,
+    /// Frames in a window, before any zero padding
+    window: usize,
+    sample_rate: u32,
+    banding: Banding,
+}
+
+/// Which bins make up each band and how much each one counts, built once and applied to
+/// every window until the bands, fft size, window size, sample rate or banding change
+#[derive(Default)]
+pub struct Filterbank {
+    filters: Vec<Filter>,
+    bands: Arc<[Band]>,
+    layout: Option<Layout>,
+}
+
+impl Filterbank {
+    /// Builds the filters again if anything they depend on changed
+    pub fn update(
+        &mut self,
+        bands: usize,
+        bins: usize,
+        window: usize,
+        sample_rate: u32,
+        banding: &Banding,
+    ) {
+        let unchanged = self.layout.as_ref().is_some_and(|layout| {
+            (layout.bands, layout.bins, layout.window, layout.sample_rate)
+                == (bands, bins, window, sample_rate)
+                && layout.banding == *banding
+        });
+        if unchanged {
+            return;
+        }
+
+        let layout = Layout {
+            bands,
+            bins,
+            window,
+            sample_rate,
+            banding: banding.clone(),
+        };
+        self.build(&layout);
+        self.layout = Some(layout);
+    }
+
+    #[profiling::function]
+    fn build(&mut self, layout: &Layout) {
+        self.filters.clear();
+        self.bands = bands::layout(layout.bands, &layout.banding).into();
+        if layout.bins < 2 {
+            return;
+        }
+
+        let hz_per = (layout.sample_rate as f32 / 2.0) / (layout.bins as f32 - 1.0);
+        let triangular = bands::is_triangular(&layout.banding.scale);
+
+        // zero padding spreads everything over more bins, which sums pick up more of, so they're
+        // divided by how many bins there are. Averages stay put, so they're divided by how many
+        // bins there would be without padding, which keeps levels the same at any padding
+        let unpadded = (layout.window / 2 + 1) as f32;
+
+        for &Band { low, center, high } in self.bands.iter() {
+            let (low, center, high) = (low / hz_per, center / hz_per, high / hz_per);
+
+            // rectangles sum their bins, and triangles do too unless they're normalized by area
+            let (mut filter, averaged) = match triangular {
+                true => {
+                    let averaged = layout.banding.normalization == FilterNormalization::Area;
+                    (Filter::triangle(low, center, high, layout.bins), averaged)
+                }
+                false => (Filter::rectangle(low, high, layout.bins), false),
+            };
+            let scale = match averaged {
+                false => (layout.bins as f32).recip(),
+                true => match filter.weights.iter().sum::<f32>() {
+                    area if area > 0.0 => (area * unpadded).recip(),
+                    _ => 0.0,
+                },
+            };
+            filter
+                .weights
+                .iter_mut()
+                .for_each(|weight| *weight *= scale);
+
+            self.filters.push(filter);
+        }
+    }
+
+    pub fn bands(&self) -> &Arc<[Band]> {
+        &self.bands
+    }
+
+    #[profiling::function]
+    pub fn apply(&self, channel: &mut Channel) {
+        for (band, filter) in channel.band_magnitudes.iter_mut().zip(&self.filters) {
+            *band = filter.apply(&channel.fft_magnitudes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+    use crate::config::{FrequencyCutoff, FrequencyScale};
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const WINDOW: usize = 512;
+
+    /// The loudest band for a Hann-windowed 1 kHz sine, zero padded `padding` times over
+    fn tone_level(
+        scale: FrequencyScale,
+        normalization: FilterNormalization,
+        padding: usize,
+    ) -> f32 {
+        let fft_size = WINDOW * padding;
+        let mut channel = Channel::empty(fft_size);
+        for (bin, magnitude) in channel.fft_magnitudes.iter_mut().enumerate() {
+            let (mut re, mut im) = (0.0, 0.0);
+            for n in 0..WINDOW {
+                let hann = 0.5 - 0.5 * (TAU * n as f32 / WINDOW as f32).cos();
+                let sample = hann * (TAU * 1_000.0 * n as f32 / SAMPLE_RATE as f32).sin();
+                let phase = TAU * (bin * n % fft_size) as f32 / fft_size as f32;
+                re += sample * phase.cos();
+                im -= sample * phase.sin();
+            }
+            *magnitude = re.hypot(im);
+        }
+
+        let banding = Banding {
+            frequency_cutoff: FrequencyCutoff {
+                low: 20.0,
+                high: 20_000.0,
+            },
+            scale,
+            normalization,
+            fixed_bands: false,
+        };
+        let bins = channel.fft_magnitudes.len();
+        let mut filterbank = Filterbank::default();
+        filterbank.update(24, bins, WINDOW, SAMPLE_RATE, &banding);
+
+        channel.band_magnitudes = vec![0.0; 24];
+        filterbank.apply(&mut channel);
+        channel.band_magnitudes.into_iter().fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn levels_dont_depend_on_zero_padding() {
+        let filters = [
+            (FrequencyScale::Mel, FilterNormalization::Peak),
+            (FrequencyScale::Mel, FilterNormalization::Area),
+            (FrequencyScale::Logarithmic, FilterNormalization::Peak),
+        ];
+        for (scale, normalization) in filters {
+            let unpadded = tone_level(scale.clone(), normalization, 1);
+            for padding in [2, 4] {
+                let padded = tone_level(scale.clone(), normalization, padding);
+                let db = 20.0 * (padded / unpadded).log10();
+                assert!(
+                    db.abs() < 0.5,
+                    "{scale:?} with {normalization:?} at {padding}x is {db} dB off"
+                );
+            }
+        }
+    }
+}
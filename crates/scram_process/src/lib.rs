@@ -1,19 +1,22 @@
-use std::time::Instant;
+use std::{sync::Arc, time::Instant};
 
 pub mod config;
 use config::*;
 
 mod buffer;
-pub use buffer::{Buffer, Source};
+pub use buffer::{Buffer, Format, Source};
 
 mod band_smoothing;
 use band_smoothing::apply_band_smoothing;
 
 mod bands;
-use bands::aggregate_bands;
+pub use bands::Band;
 
-mod magnitudes;
-use magnitudes::calculate_magnitudes;
+mod cqt;
+use cqt::ConstantQ;
+
+mod filterbank;
+use filterbank::Filterbank;
 
 mod peak_smoothing;
 use peak_smoothing::apply_peak_smoothing;
@@ -21,12 +24,28 @@ use peak_smoothing::apply_peak_smoothing;
 mod preprocess;
 use preprocess::preprocess;
 
-mod rfft;
-use rfft::apply_rfft;
+mod resample;
+pub use resample::Resampler;
+
+pub mod fft;
+use fft::Fft;
 
 mod scaling;
 use scaling::apply_scaling;
 
+mod silence;
+use silence::SilenceDetector;
+pub use silence::SilenceState;
+
+mod tilt;
+use tilt::TiltTable;
+
+mod weighting;
+use weighting::WeightingTable;
+
+mod window;
+use window::WindowTable;
+
 mod background;
 pub use background::Slot;
 
@@ -55,73 +74,311 @@ struct Channel {
     band_magnitudes: Vec<f32>,
     smoothed_band_magnitudes: Vec<f32>,
     frequencies: Vec<Frequency>,
+
+    /// Past samples, for transforms that look further back than one window
+    history: Vec<f32>,
 }
 
 impl Channel {
-    fn empty(size: usize) -> Self {
+    fn empty(fft_size: usize) -> Self {
         Self {
-            fft_input: vec![0.0; size].into_boxed_slice(),
-            fft_magnitudes: vec![0.0; size / 2 + 1].into_boxed_slice(),
+            fft_input: vec![0.0; fft_size].into_boxed_slice(),
+            fft_magnitudes: vec![0.0; fft_size / 2 + 1].into_boxed_slice(),
             band_magnitudes: Vec::new(),
             smoothed_band_magnitudes: Vec::new(),
             frequencies: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    fn resize_fft(&mut self, fft_size: usize) {
+        self.fft_input = vec![0.0; fft_size].into_boxed_slice();
+        self.fft_magnitudes = vec![0.0; fft_size / 2 + 1].into_boxed_slice();
+    }
+}
+
+/// How many bands there are, worked out again only when something it depends on changes
+#[derive(Default)]
+struct BandCount {
+    layout: Option<(usize, u32, Analysis, Banding)>,
+    count: usize,
+}
+
+impl BandCount {
+    fn update(&mut self, requested: usize, sample_rate: u32, config: &Config) -> usize {
+        let (analysis, banding) = (config.analysis, &config.banding);
+        let unchanged = self.layout.as_ref().is_some_and(
+            |(old_requested, old_rate, old_analysis, old_banding)| {
+                (*old_requested, *old_rate, *old_analysis) == (requested, sample_rate, analysis)
+                    && old_banding == banding
+            },
+        );
+        if unchanged {
+            return self.count;
         }
+
+        self.count = match analysis {
+            Analysis::Fft => bands::count(requested, banding),
+            Analysis::ConstantQ {
+                bins_per_octave,
+                min_frequency,
+            } => {
+                let bins = cqt::bins(bins_per_octave, min_frequency, sample_rate, banding);
+                cqt::count(requested, bins.len(), banding)
+            }
+        };
+        self.layout = Some((requested, sample_rate, analysis, banding.clone()));
+        self.count
     }
 }
 
 pub struct Processor {
     config: Config,
+    /// The last config that validated, which one changed through
+    /// [`config_mut`](Processor::config_mut) goes back to when it doesn't
+    validated: Config,
     sample_rate: u32,
+    channels: usize,
+    fft: Box<dyn Fft>,
+    filterbank: Filterbank,
+    cqt: ConstantQ,
+    window: WindowTable,
+    weighting: WeightingTable,
+    tilt: TiltTable,
+    silence: SilenceDetector,
+    /// Bands asked for, which the scale may override
+    requested_bands: usize,
+    band_count: BandCount,
 
     left: Channel,
     right: Channel,
 
     last_update: Instant,
     sample_size: usize,
+    /// Why the config had to fall back, until someone asks
+    error: Option<anyhow::Error>,
 }
 
 impl Processor {
     pub const MIN_SAMPLE_SIZE: usize = 32;
-    pub const MAX_SAMPLE_SIZE: usize = 4096;
-
-    pub fn new(sample_rate: u32, sample_size: usize, config: Config) -> anyhow::Result<Self> {
-        let sample_size = sample_size
-            .clamp(Self::MIN_SAMPLE_SIZE, Self::MAX_SAMPLE_SIZE)
-            .next_power_of_two();
+    pub const MAX_SAMPLE_SIZE: usize = 32768;
+
+    /// Analyses windows of `sample_size` frames per channel, rounded up to a size the
+    /// enabled [`fft`] backends can transform. The fft itself is longer with [`Config::zero_padding`]
+    ///
+    /// Fails if the config doesn't [validate](Config::validate)
+    pub fn new(
+        sample_rate: u32,
+        channels: u16,
+        sample_size: usize,
+        config: Config,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(channels > 0, "at least one channel is required");
+        config.validate(sample_rate, channels)?;
+
+        let sample_size = fft::fit(sample_size.clamp(Self::MIN_SAMPLE_SIZE, Self::MAX_SAMPLE_SIZE));
+        let fft_size = padded_size(sample_size, &config);
+        let fft = fft::plan(fft_size)?;
 
         Ok(Self {
+            validated: config.clone(),
             config,
             sample_rate,
-            left: Channel::empty(sample_size / 2),
-            right: Channel::empty(sample_size / 2),
+            channels: channels as usize,
+            fft,
+            filterbank: Filterbank::default(),
+            cqt: ConstantQ::default(),
+            window: WindowTable::default(),
+            weighting: WeightingTable::default(),
+            tilt: TiltTable::default(),
+            silence: SilenceDetector::default(),
+            requested_bands: 0,
+            band_count: BandCount::default(),
+            left: Channel::empty(fft_size),
+            right: Channel::empty(fft_size),
             last_update: Instant::now(),
             sample_size,
+            error: None,
         })
     }
 
+    /// How many interleaved samples are read from a [`Buffer`] per update
+    pub fn read_size(&self) -> usize {
+        self.sample_size * self.channels
+    }
+
+    /// How many interleaved samples each update moves forward by
+    pub fn hop_size(&self) -> usize {
+        self.config.hop.frames(self.sample_size) * self.channels
+    }
+
+    /// Processes the next window, if the buffer has one. Returns whether there are new
+    /// frequencies to publish
+    ///
+    /// Switches formats along with the buffer, before the first window in the new one. When
+    /// the config has to fall back, [`take_error`](Processor::take_error) says why
     #[profiling::function]
     pub fn update(&mut self, buffer: &mut dyn Buffer) -> bool {
+        self.revalidate();
+        if let Some(format) = buffer.next_format() {
+            if let Err(err) = self.switch_format(format.sample_rate, format.channels) {
+                self.error = Some(err);
+            }
+        }
+
+        let read_size = self.read_size();
+        let hop_size = self.hop_size();
         let samples = {
             profiling::scope!("read samples");
-            match buffer.read_samples(self.sample_size) {
+            match buffer.read_samples(read_size, hop_size) {
                 Some(samples) => samples,
                 None => return false,
             }
         };
 
-        if samples.len() != self.sample_size {
+        if samples.len() != read_size {
             return false;
         }
 
-        self.process_samples(samples);
-        true
+        self.process_samples(samples)
+    }
+
+    /// Why the config last had to fall back, if it did since this was last asked, after a
+    /// format switch or a change through [`config_mut`](Processor::config_mut)
+    pub fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+
+    /// Follows a source that switched formats, such as a device that was reconnected
+    ///
+    /// Fails, and keeps the old format, if the config doesn't [validate](Config::validate) for
+    /// the new one
+    pub fn set_format(&mut self, sample_rate: u32, channels: u16) -> anyhow::Result<()> {
+        anyhow::ensure!(channels > 0, "at least one channel is required");
+        self.config.validate(sample_rate, channels)?;
+
+        self.validated = self.config.clone();
+        self.sample_rate = sample_rate;
+        self.channels = channels as usize;
+        Ok(())
+    }
+
+    /// Follows a source that switched formats whether or not the config can work with the new
+    /// one, since the source's windows are in the new format either way. The channel map falls
+    /// back to [`ChannelMap::Downmix`] when that's enough, and the whole config to its defaults
+    /// when it isn't
+    ///
+    /// Fails, after switching, with why the config had to fall back
+    pub fn switch_format(&mut self, sample_rate: u32, channels: u16) -> anyhow::Result<()> {
+        anyhow::ensure!(channels > 0, "at least one channel is required");
+        let Err(err) = self.config.validate(sample_rate, channels) else {
+            return self.set_format(sample_rate, channels);
+        };
+
+        let downmix = Config {
+            channel_map: ChannelMap::Downmix,
+            ..self.config.clone()
+        };
+        let (config, fallback) = match downmix.validate(sample_rate, channels) {
+            Ok(()) => (downmix, "downmixing"),
+            Err(_) => (Config::default(), "the default settings"),
+        };
+        self.config = config;
+        self.set_format(sample_rate, channels)?;
+
+        Err(err.context(format!(
+            "switched to {sample_rate} Hz and {channels} channels, and fell back to {fallback}"
+        )))
+    }
+
+    /// Swaps in another [`Fft`] backend, which has to be planned for [`Processor::fft_size`].
+    /// Changing the zero padding afterwards plans a default backend again
+    pub fn set_fft(&mut self, fft: Box<dyn Fft>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            fft.size() == self.fft_size(),
+            "the fft is planned for {} samples, not {}",
+            fft.size(),
+            self.fft_size()
+        );
+        self.fft = fft;
+        Ok(())
+    }
+
+    /// Frames per channel in each analysis window
+    pub fn sample_size(&self) -> usize {
+        self.sample_size
+    }
+
+    /// Samples each fft runs over, the window plus any zero padding
+    pub fn fft_size(&self) -> usize {
+        self.left.fft_input.len()
     }
 
+    /// Plans the fft again if the zero padding changed since it was last planned
+    fn sync_fft_size(&mut self) {
+        let fft_size = padded_size(self.sample_size, &self.config);
+        if fft_size == self.fft_size() {
+            return;
+        }
+
+        // padded sizes are always within what the backends can do, see `padded_size`
+        let Ok(fft) = fft::plan(fft_size) else {
+            return;
+        };
+        self.fft = fft;
+        self.left.resize_fft(fft_size);
+        self.right.resize_fft(fft_size);
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Swaps in another config, which takes effect from the next window
+    ///
+    /// Fails, and keeps the old config, if the new one doesn't [validate](Config::validate)
+    pub fn set_config(&mut self, config: Config) -> anyhow::Result<()> {
+        config.validate(self.sample_rate, self.channels as u16)?;
+        self.validated = config.clone();
+        self.config = config;
+        Ok(())
+    }
+
+    /// Changes the config in place. It's [validated](Config::validate) before the next window,
+    /// and when it doesn't, the last config that did comes back and
+    /// [`take_error`](Processor::take_error) says why
     pub fn config_mut(&mut self) -> &mut Config {
         &mut self.config
     }
 
+    /// Checks a config changed through [`config_mut`](Processor::config_mut)
+    fn revalidate(&mut self) {
+        if self.config == self.validated {
+            return;
+        }
+
+        match self.config.validate(self.sample_rate, self.channels as u16) {
+            Ok(()) => self.validated = self.config.clone(),
+            Err(err) => {
+                self.config = self.validated.clone();
+                self.error = Some(err.context("kept the last config that worked"));
+            }
+        }
+    }
+
+    /// Asks for `bands` bands, unless the scale has a fixed set of its own
     pub fn set_bands(&mut self, bands: usize) {
+        self.requested_bands = bands;
+        let bands = self.band_count();
+        self.resize_bands(bands);
+    }
+
+    fn band_count(&mut self) -> usize {
+        self.band_count
+            .update(self.requested_bands, self.sample_rate, &self.config)
+    }
+
+    fn resize_bands(&mut self, bands: usize) {
         let bar = Frequency::empty();
         for channel in [&mut self.left, &mut self.right] {
             channel.band_magnitudes.resize(bands, 0.0);
@@ -132,28 +389,69 @@ impl Processor {
         }
     }
 
+    /// Whether the input was silent, as of the last processed window
+    pub fn silence(&self) -> SilenceState {
+        self.silence.state()
+    }
+
     pub fn current_frequencies(&self) -> [&[Frequency]; 2] {
         [&self.left.frequencies, &self.right.frequencies]
     }
 
+    /// Where each of the [`current_frequencies`](Self::current_frequencies) sits in the spectrum,
+    /// as of the last processed window
+    pub fn bands(&self) -> Arc<[Band]> {
+        match self.config.analysis {
+            Analysis::Fft => self.filterbank.bands().clone(),
+            Analysis::ConstantQ { .. } => self.cqt.bands().clone(),
+        }
+    }
+
+    /// Analyses a window of interleaved samples. Returns whether there are new frequencies to
+    /// publish, which there aren't once the input is silent unless [`SilenceBehavior::Publish`]
+    /// is set
     #[profiling::function]
-    pub fn process_samples(&mut self, samples: &[f32]) {
+    pub fn process_samples(&mut self, samples: &[f32]) -> bool {
         let current = Instant::now();
         let dt = current.duration_since(self.last_update).as_secs_f32();
         self.last_update = current;
 
-        let (left, right) = (&mut self.left, &mut self.right);
-
-        preprocess(samples, left, right, &self.config.window, self.sample_size);
+        self.revalidate();
+        self.sync_fft_size();
+        let bands = self.band_count();
+        if bands != self.left.band_magnitudes.len() {
+            self.resize_bands(bands);
+        }
 
-        apply_rfft(&mut left.fft_input);
-        apply_rfft(&mut right.fft_input);
+        let hop = self.config.hop.frames(self.sample_size);
+        let elapsed = hop as f32 / self.sample_rate.max(1) as f32;
+        let silence = self.silence.update(samples, elapsed, &self.config.silence);
 
-        calculate_magnitudes(&mut left.fft_magnitudes, &left.fft_input);
-        calculate_magnitudes(&mut right.fft_magnitudes, &right.fft_input);
+        match (silence, self.config.silence.behavior) {
+            (SilenceState::Silent, SilenceBehavior::Stop) => {
+                self.left.history.clear();
+                self.right.history.clear();
+                return false;
+            }
+            (SilenceState::Silent, SilenceBehavior::Decay) => {
+                let settled = [&self.left, &self.right]
+                    .into_iter()
+                    .flat_map(|channel| &channel.frequencies)
+                    .all(|bar| bar.value == 0.0 && bar.peak == 0.0);
+                if settled {
+                    return false;
+                }
+
+                // nothing to analyse, the bars just fall towards nothing
+                for channel in [&mut self.left, &mut self.right] {
+                    channel.band_magnitudes.fill(0.0);
+                    channel.history.clear();
+                }
+            }
+            _ => self.analyse(samples),
+        }
 
-        aggregate_bands(left, self.sample_rate, &self.config.banding);
-        aggregate_bands(right, self.sample_rate, &self.config.banding);
+        let (left, right) = (&mut self.left, &mut self.right);
 
         apply_band_smoothing(left, &self.config.band_smoothing);
         apply_band_smoothing(right, &self.config.band_smoothing);
@@ -164,6 +462,83 @@ impl Processor {
         apply_peak_smoothing(left, current, dt, &self.config.peak_smoothing);
         apply_peak_smoothing(right, current, dt, &self.config.peak_smoothing);
 
-        // TODO silence detection
+        true
+    }
+
+    /// Turns a window into band magnitudes, with the configured transform
+    fn analyse(&mut self, samples: &[f32]) {
+        // the constant-Q kernels bring their own windows
+        let window = match self.config.analysis {
+            Analysis::Fft => &self.config.window,
+            Analysis::ConstantQ { .. } => &Window::None,
+        };
+        self.window.update(window, self.sample_size);
+
+        let (left, right) = (&mut self.left, &mut self.right);
+
+        preprocess(
+            samples,
+            left,
+            right,
+            self.window.coefficients(),
+            &self.config.channel_map,
+            self.channels,
+        );
+
+        match self.config.analysis {
+            Analysis::Fft => {
+                self.fft
+                    .magnitudes(&mut left.fft_input, &mut left.fft_magnitudes);
+                self.fft
+                    .magnitudes(&mut right.fft_input, &mut right.fft_magnitudes);
+
+                let (weighting, bins) = (self.config.weighting, left.fft_magnitudes.len());
+                self.weighting.update(weighting, bins, self.sample_rate);
+                self.weighting.apply(&mut left.fft_magnitudes);
+                self.weighting.apply(&mut right.fft_magnitudes);
+
+                self.filterbank.update(
+                    left.band_magnitudes.len(),
+                    left.fft_magnitudes.len(),
+                    self.sample_size,
+                    self.sample_rate,
+                    &self.config.banding,
+                );
+                self.filterbank.apply(left);
+                self.filterbank.apply(right);
+
+                left.history.clear();
+                right.history.clear();
+            }
+            Analysis::ConstantQ {
+                bins_per_octave,
+                min_frequency,
+            } => {
+                self.cqt.update(
+                    left.band_magnitudes.len(),
+                    bins_per_octave,
+                    min_frequency,
+                    self.sample_rate,
+                    &self.config.banding,
+                    self.config.weighting,
+                );
+
+                let window = samples.len() / self.channels;
+                let fresh = self.config.hop.frames(self.sample_size);
+                self.cqt.apply(left, window, fresh);
+                self.cqt.apply(right, window, fresh);
+            }
+        }
+
+        self.tilt.update(self.config.tilt, &self.bands());
+        self.tilt.apply(&mut self.left);
+        self.tilt.apply(&mut self.right);
     }
 }
+
+/// The fft size for a window of `sample_size` frames, capped at the largest window so every
+/// backend can still plan it
+fn padded_size(sample_size: usize, config: &Config) -> usize {
+    let padded = sample_size.saturating_mul(config.zero_padding.max(1));
+    fft::fit(padded.min(Processor::MAX_SAMPLE_SIZE))
+}
@@ -1,25 +1,43 @@
-use std::f32::consts::TAU;
+use std::f32::consts::FRAC_1_SQRT_2;
 
-use super::{Channel, Window};
+use super::{Channel, ChannelMap};
 
-#[inline(always)]
-fn none(_d: f32, _n: f32) -> f32 {
-    1.0
-}
-
-#[inline(always)]
-fn hann(d: f32, n: f32) -> f32 {
-    0.5 * (1.0 - (TAU * d / n - 1.0).cos())
-}
+/// How much `channel` (of `channels`) contributes to the left and right sides. The front pair
+/// passes as it is, so whatever is mixed to it keeps the level it would have in stereo, and the
+/// center and surrounds are folded in 3 dB down, like the ITU-R BS.775 downmix
+fn channel_weights(map: &ChannelMap, channels: usize, channel: usize) -> [f32; 2] {
+    const CENTER: [f32; 2] = [FRAC_1_SQRT_2, FRAC_1_SQRT_2];
+    const LEFT: [f32; 2] = [1.0, 0.0];
+    const RIGHT: [f32; 2] = [0.0, 1.0];
+    const SURROUND_LEFT: [f32; 2] = [FRAC_1_SQRT_2, 0.0];
+    const SURROUND_RIGHT: [f32; 2] = [0.0, FRAC_1_SQRT_2];
 
-#[inline(always)]
-fn hamming(d: f32, n: f32) -> f32 {
-    0.54 - 0.46 * (TAU * d / n - 1.0).cos()
-}
+    let &ChannelMap::Pair { left, right } = map else {
+        return match (channels, channel) {
+            (1, _) => [1.0, 1.0],
+            (_, 0) => LEFT,
+            (_, 1) => RIGHT,
+            // L R C
+            (3, _) => CENTER,
+            // FL FR BL BR
+            (4, 2) => SURROUND_LEFT,
+            (4, _) => SURROUND_RIGHT,
+            // FL FR FC BL BR
+            (5, 2) => CENTER,
+            (5, 3) => SURROUND_LEFT,
+            (5, _) => SURROUND_RIGHT,
+            // FL FR FC LFE BL BR (SL SR ..)
+            (_, 2) => CENTER,
+            (_, 3) => [0.0, 0.0],
+            (_, c) if c % 2 == 0 => SURROUND_LEFT,
+            _ => SURROUND_RIGHT,
+        };
+    };
 
-#[inline(always)]
-fn blackman(d: f32, n: f32) -> f32 {
-    0.42 - 0.5 * (TAU * d / n - 1.0).cos() + 0.08 * (TAU * 2.0 * d / n - 1.0).cos()
+    [
+        (left == channel) as u8 as f32,
+        (right == channel) as u8 as f32,
+    ]
 }
 
 #[profiling::function]
@@ -27,21 +45,83 @@ pub fn preprocess(
     samples: &[f32],
     left: &mut Channel,
     right: &mut Channel,
-    config: &Window,
-    sample_size: usize,
+    window: &[f32],
+    channel_map: &ChannelMap,
+    channels: usize,
 ) {
-    let f = match config {
-        Window::None => none,
-        Window::Hann => hann,
-        Window::Hamming => hamming,
-        Window::Blackman => blackman,
-    };
+    for ((i, frame), &t) in samples.chunks_exact(channels).enumerate().zip(window) {
+        let (l, r) = match *frame {
+            [l, r] if matches!(channel_map, ChannelMap::Downmix) => (l, r),
+            _ => frame
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(l, r), (c, &s)| {
+                    let [wl, wr] = channel_weights(channel_map, channels, c);
+                    (l + s * wl, r + s * wr)
+                }),
+        };
 
-    let nyquist = sample_size as f32;
-    for (i, chunk) in samples.chunks_exact(2).enumerate() {
-        let t = f(i as f32, nyquist);
-        let &[l, r] = chunk else { unreachable!() };
+        // samples are floats, so a side that goes over full scale doesn't clip
         left.fft_input[i] = l * t;
         right.fft_input[i] = r * t
     }
+
+    // the fft works in place, so the padding has to be cleared out every time
+    let frames = samples.len() / channels;
+    left.fft_input[frames..].fill(0.0);
+    right.fft_input[frames..].fill(0.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The left and right sides of a single frame, without a window
+    fn downmix(frame: &[f32], map: &ChannelMap) -> [f32; 2] {
+        let (mut left, mut right) = (Channel::empty(2), Channel::empty(2));
+        preprocess(frame, &mut left, &mut right, &[1.0], map, frame.len());
+        [left.fft_input[0], right.fft_input[0]]
+    }
+
+    fn assert_sides(actual: [f32; 2], expected: [f32; 2]) {
+        let close = actual
+            .iter()
+            .zip(expected)
+            .all(|(actual, expected)| (actual - expected).abs() < 1e-6);
+        assert!(close, "{actual:?} isn't {expected:?}");
+    }
+
+    #[test]
+    fn mono_goes_to_both_sides() {
+        assert_sides(downmix(&[0.5], &ChannelMap::Downmix), [0.5, 0.5]);
+    }
+
+    #[test]
+    fn stereo_passes_as_it_is() {
+        assert_sides(downmix(&[0.5, -0.25], &ChannelMap::Downmix), [0.5, -0.25]);
+
+        let swapped = ChannelMap::Pair { left: 1, right: 0 };
+        assert_sides(downmix(&[0.5, -0.25], &swapped), [-0.25, 0.5]);
+    }
+
+    #[test]
+    fn surround_keeps_the_level_of_the_front_pair() {
+        let map = ChannelMap::Downmix;
+        // FL FR FC LFE BL BR
+        assert_sides(
+            downmix(&[0.5, -0.25, 0.0, 0.0, 0.0, 0.0], &map),
+            [0.5, -0.25],
+        );
+        // the center goes to both sides 3 dB down, like a sound panned to the middle
+        assert_sides(
+            downmix(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0], &map),
+            [FRAC_1_SQRT_2; 2],
+        );
+        // the LFE is left out, and the surrounds stay on their own sides
+        assert_sides(downmix(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0], &map), [0.0, 0.0]);
+        assert_sides(
+            downmix(&[0.0, 0.0, 0.0, 0.0, 1.0, -1.0], &map),
+            [FRAC_1_SQRT_2, -FRAC_1_SQRT_2],
+        );
+    }
 }
@@ -0,0 +1,350 @@
+use std::f64::consts::{PI, TAU};
+
+use crate::{Buffer, Format};
+
+/// Converts another [`Buffer`] to a fixed sample rate, so analysis looks the same at any device rate
+///
+/// Uses a windowed-sinc filter, which also keeps content above the new Nyquist frequency from
+/// aliasing when decimating. The output rate is fixed, the input's follows the inner buffer's
+/// [format](Buffer::next_format), or can be changed with [`set_format`](Resampler::set_format)
+pub struct Resampler<B> {
+    inner: B,
+    to_rate: u32,
+    channels: usize,
+    /// Input frames per output frame
+    step: f64,
+    /// `PHASES + 1` rows of `taps` coefficients, the last one a whole frame on from the first
+    kernel: Box<[f32]>,
+    taps: usize,
+
+    /// Interleaved input frames, where the next output frame lands at `position`
+    history: Vec<f32>,
+    position: f64,
+    window: Vec<f32>,
+}
+
+impl<B: Buffer> Resampler<B> {
+    /// Fractional positions between two input frames that have their own coefficients, the
+    /// ones in between are interpolated
+    const PHASES: usize = 256;
+    /// Zero crossings of the sinc on either side of the center
+    const ZERO_CROSSINGS: usize = 16;
+    /// Input frames read at a time, at least
+    const CHUNK_FRAMES: usize = 256;
+    /// How far below the new Nyquist frequency the filter starts rolling off
+    const ROLLOFF: f64 = 0.95;
+
+    pub fn new(inner: B, from_rate: u32, to_rate: u32, channels: u16) -> anyhow::Result<Self> {
+        let mut this = Self {
+            inner,
+            to_rate,
+            channels: 1,
+            step: 1.0,
+            kernel: Box::default(),
+            taps: 0,
+            history: Vec::new(),
+            position: 0.0,
+            window: Vec::new(),
+        };
+        this.set_format(from_rate, channels)?;
+        Ok(this)
+    }
+
+    /// The rate everything comes out at
+    pub fn to_rate(&self) -> u32 {
+        self.to_rate
+    }
+
+    /// Starts over with input in another format, such as a reconnected device's
+    pub fn set_format(&mut self, from_rate: u32, channels: u16) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            from_rate > 0 && self.to_rate > 0,
+            "sample rates must be positive"
+        );
+        anyhow::ensure!(channels > 0, "at least one channel is required");
+
+        let step = from_rate as f64 / self.to_rate as f64;
+
+        // when decimating, the filter has to cut off at the new Nyquist frequency, which
+        // stretches it out over more input frames. At the same rate there's nothing to cut
+        // off, and the filter passes every frame through as is
+        let cutoff = match from_rate == self.to_rate {
+            true => 1.0,
+            false => step.recip().min(1.0) * Self::ROLLOFF,
+        };
+        let half = (Self::ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+        let taps = 2 * half;
+
+        let mut kernel = vec![0.0; (Self::PHASES + 1) * taps].into_boxed_slice();
+        for (phase, row) in kernel.chunks_exact_mut(taps).enumerate() {
+            let fraction = phase as f64 / Self::PHASES as f64;
+            for (tap, coefficient) in row.iter_mut().enumerate() {
+                // distance from the output frame to this input frame
+                let x = tap as f64 - (half - 1) as f64 - fraction;
+                *coefficient = (sinc(cutoff * x) * blackman(x, half as f64)) as f32;
+            }
+
+            // unity gain at DC, whatever the phase
+            let sum = row.iter().sum::<f32>();
+            row.iter_mut().for_each(|coefficient| *coefficient /= sum);
+        }
+
+        let channels = channels as usize;
+        self.channels = channels;
+        self.step = step;
+        self.kernel = kernel;
+        self.taps = taps;
+        // start with silence before the first input frame, so the filter has something to look back at
+        self.history = vec![0.0; (half - 1) * channels];
+        self.position = (half - 1) as f64;
+        self.window.clear();
+        Ok(())
+    }
+
+    fn frames(&self) -> usize {
+        self.history.len() / self.channels
+    }
+
+    /// First input frame the next output frame needs
+    fn first_tap(&self) -> usize {
+        self.position as usize + 1 - self.taps / 2
+    }
+
+    fn next_frame(&mut self) -> Option<()> {
+        while self.first_tap() + self.taps > self.frames() {
+            let chunk = Self::CHUNK_FRAMES * self.channels;
+            let samples = self.inner.read_stream(chunk)?;
+            self.history.extend_from_slice(samples);
+        }
+
+        // the position falls between two rows of the kernel, so blend them
+        let first = self.first_tap();
+        let phase = self.position.fract() * Self::PHASES as f64;
+        let (row, blend) = (phase as usize, phase.fract() as f32);
+        let below = &self.kernel[row * self.taps..][..self.taps];
+        let above = &self.kernel[(row + 1) * self.taps..][..self.taps];
+
+        let frames = &self.history[first * self.channels..][..self.taps * self.channels];
+        for channel in 0..self.channels {
+            let sample = frames
+                .iter()
+                .skip(channel)
+                .step_by(self.channels)
+                .zip(below.iter().zip(above))
+                .map(|(sample, (below, above))| sample * (below + (above - below) * blend))
+                .sum();
+            self.window.push(sample);
+        }
+
+        self.position += self.step;
+        Some(())
+    }
+
+    /// Drops input frames that no output frame needs anymore
+    fn trim(&mut self) {
+        let stale = self.first_tap();
+        self.history.drain(..stale * self.channels);
+        self.position -= stale as f64;
+    }
+
+    /// When more than two windows of input are waiting, skips ahead to the last one
+    fn catch_up(&mut self, sample_size: usize) {
+        let window = ((sample_size / self.channels) as f64 * self.step).ceil() as usize;
+        let waiting = self.frames().saturating_sub(self.first_tap() + self.taps);
+        if waiting <= 2 * window {
+            return;
+        }
+
+        let skip = (waiting - window) * self.channels;
+        let first = self.first_tap() * self.channels;
+        self.history.drain(first..first + skip);
+        self.inner.report_dropped(skip);
+        // what's kept of the last window doesn't line up with what comes next anymore
+        self.window.clear();
+    }
+}
+
+impl<B: Buffer> Buffer for Resampler<B> {
+    #[profiling::function]
+    fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+        self.catch_up(sample_size);
+
+        // keep the tail of the last window, and only resample what's new
+        let keep = sample_size.saturating_sub(hop_size).min(self.window.len());
+        self.window.drain(..self.window.len() - keep);
+
+        let frames = (sample_size - self.window.len()) / self.channels;
+        for _ in 0..frames {
+            self.next_frame()?;
+        }
+        self.trim();
+
+        Some(&self.window)
+    }
+
+    fn report_dropped(&mut self, samples: usize) {
+        let frames = samples / self.channels;
+        let input = (frames as f64 * self.step).round() as usize;
+        self.inner.report_dropped(input * self.channels);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+
+    fn next_format(&mut self) -> Option<Format> {
+        let format = self.inner.next_format()?;
+        // a format that can't be resampled goes through as is, for the reader to turn down
+        match self.set_format(format.sample_rate, format.channels) {
+            Ok(()) => Some(Format {
+                sample_rate: self.to_rate,
+                ..format
+            }),
+            Err(_) => Some(format),
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    match x.abs() < 1e-9 {
+        true => 1.0,
+        false => (PI * x).sin() / (PI * x),
+    }
+}
+
+/// Blackman window over `-half..=half`, centered on zero
+fn blackman(x: f64, half: f64) -> f64 {
+    if x.abs() >= half {
+        return 0.0;
+    }
+    let t = (x + half) / (2.0 * half);
+    0.42 - 0.5 * (TAU * t).cos() + 0.08 * (2.0 * TAU * t).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sine wave on every channel, or a count of every sample read when `hz` is zero
+    struct Stream {
+        hz: f64,
+        sample_rate: f64,
+        channels: usize,
+        read: usize,
+        window: Vec<f32>,
+        /// Reported once, before the first read
+        format: Option<Format>,
+    }
+
+    impl Stream {
+        fn new(hz: f64, sample_rate: u32, channels: usize) -> Self {
+            Self {
+                hz,
+                sample_rate: sample_rate as f64,
+                channels,
+                read: 0,
+                window: Vec::new(),
+                format: None,
+            }
+        }
+    }
+
+    impl Buffer for Stream {
+        fn read_samples(&mut self, sample_size: usize, hop_size: usize) -> Option<&[f32]> {
+            assert_eq!(sample_size, hop_size);
+            self.window = (self.read..self.read + sample_size)
+                .map(|n| match self.hz {
+                    0.0 => n as f32,
+                    hz => {
+                        let frame = (n / self.channels) as f64;
+                        (TAU * hz * frame / self.sample_rate).sin() as f32
+                    }
+                })
+                .collect();
+            self.read += sample_size;
+            Some(&self.window)
+        }
+
+        fn next_format(&mut self) -> Option<Format> {
+            self.format.take()
+        }
+    }
+
+    /// How many times the signal goes from negative to positive
+    fn rising_edges(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count()
+    }
+
+    #[test]
+    fn same_rate_passes_every_sample() {
+        let mut resampler = Resampler::new(Stream::new(0.0, 48_000, 2), 48_000, 48_000, 2).unwrap();
+        for hop in 0..16 {
+            let window = resampler.read_samples(512, 256).unwrap();
+            let expected = hop * 256..hop * 256 + 512;
+            for (sample, n) in window.iter().zip(expected) {
+                assert!((sample - n as f32).abs() < 1e-3, "{sample} isn't {n}");
+            }
+        }
+    }
+
+    #[test]
+    fn reads_as_much_input_as_the_rates_call_for() {
+        let mut resampler = Resampler::new(Stream::new(0.0, 48_000, 1), 48_000, 32_000, 1).unwrap();
+        for _ in 0..64 {
+            resampler.read_samples(1024, 1024).unwrap();
+        }
+        let expected = 64 * 1024 * 3 / 2;
+        let read = resampler.inner.read;
+        assert!(read >= expected && read <= expected + 2048, "{read}");
+    }
+
+    #[test]
+    fn follows_a_format_change() {
+        let mut resampler =
+            Resampler::new(Stream::new(1_000.0, 48_000, 1), 48_000, 24_000, 1).unwrap();
+        let window = resampler.read_samples(2400, 2400).unwrap();
+        assert!(rising_edges(window).abs_diff(100) <= 1);
+
+        let format = Format {
+            sample_rate: 96_000,
+            channels: 2,
+        };
+        resampler.inner = Stream {
+            format: Some(format),
+            ..Stream::new(1_000.0, 96_000, 2)
+        };
+        let resampled = Format {
+            sample_rate: 24_000,
+            ..format
+        };
+        assert_eq!(resampler.next_format(), Some(resampled));
+        assert_eq!(resampler.next_format(), None);
+        // both channels carry the same tone, which still comes out at 1 kHz
+        let window = resampler.read_samples(4800, 4800).unwrap();
+        let left = window.iter().step_by(2).copied().collect::<Vec<_>>();
+        assert!(rising_edges(&left).abs_diff(100) <= 1);
+    }
+
+    #[test]
+    fn lands_between_input_frames() {
+        // 44.1 kHz frames fall all over the 48 kHz ones, not just on the kernel's phases
+        let mut resampler =
+            Resampler::new(Stream::new(1_000.0, 48_000, 1), 48_000, 44_100, 1).unwrap();
+        resampler.read_samples(4410, 4410).unwrap();
+        let window = resampler.read_samples(4410, 4410).unwrap();
+
+        // the filter delays nothing, so the output is the same sine at the new rate
+        let error = window
+            .iter()
+            .enumerate()
+            .map(|(n, sample)| {
+                let t = (4410 + n) as f64 / 44_100.0;
+                (sample - (TAU * 1_000.0 * t).sin() as f32).abs()
+            })
+            .fold(0.0, f32::max);
+        assert!(error < 1e-4, "{error}");
+    }
+}
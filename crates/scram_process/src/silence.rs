@@ -0,0 +1,189 @@
+use super::Silence;
+
+/// Whether the input is making any sound, as of the last processed window
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq)]
+pub enum SilenceState {
+    #[default]
+    Sound,
+    /// Under the threshold, but not for the whole hold time yet
+    Quiet,
+    /// Under the threshold for at least the hold time
+    Silent,
+}
+
+/// Follows how long the input has been under the silence threshold
+#[derive(Default)]
+pub struct SilenceDetector {
+    /// Seconds of audio under the threshold so far
+    quiet_for: f32,
+    state: SilenceState,
+}
+
+impl SilenceDetector {
+    /// Follows a window of interleaved `samples` that starts `elapsed` seconds after the last one
+    pub fn update(&mut self, samples: &[f32], elapsed: f32, config: &Silence) -> SilenceState {
+        self.state = match level(samples) < config.threshold {
+            true => {
+                self.quiet_for += elapsed;
+                match self.quiet_for >= config.hold {
+                    true => SilenceState::Silent,
+                    false => SilenceState::Quiet,
+                }
+            }
+            false => {
+                self.quiet_for = 0.0;
+                SilenceState::Sound
+            }
+        };
+        self.state
+    }
+
+    pub fn state(&self) -> SilenceState {
+        self.state
+    }
+}
+
+/// The RMS level of `samples` in dBFS, which is negative infinity for digital silence
+fn level(samples: &[f32]) -> f32 {
+    let power = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+    10.0 * power.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+    use crate::{
+        Processor,
+        config::{BandSmoothing, Config, Hop, PeakSmoothing, SilenceBehavior},
+    };
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const WINDOW: usize = 256;
+    /// Four windows of silence, as every window starts `WINDOW` frames after the last
+    const HOLD: f32 = 0.02;
+
+    fn tone() -> Vec<f32> {
+        (0..WINDOW)
+            .map(|n| 0.5 * (TAU * 1_000.0 * n as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    fn silence() -> Vec<f32> {
+        vec![0.0; WINDOW]
+    }
+
+    fn config(behavior: SilenceBehavior) -> Config {
+        Config {
+            hop: Hop::Frames(WINDOW),
+            band_smoothing: BandSmoothing::None,
+            // bars jump straight to where they're going, so tests don't depend on timing
+            peak_smoothing: PeakSmoothing {
+                attack_rate: 1e9,
+                decay_rate: 1e9,
+                decay_limit: 1e-9,
+                ..PeakSmoothing::default()
+            },
+            silence: Silence {
+                threshold: -60.0,
+                hold: HOLD,
+                behavior,
+            },
+            ..Config::default()
+        }
+    }
+
+    fn processor(behavior: SilenceBehavior) -> Processor {
+        let mut processor = Processor::new(SAMPLE_RATE, 1, WINDOW, config(behavior)).unwrap();
+        processor.set_bands(16);
+        processor
+    }
+
+    fn loudest(processor: &Processor) -> f32 {
+        let [left, _] = processor.current_frequencies();
+        left.iter().map(|bar| bar.value).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn goes_silent_after_the_hold_time() {
+        let config = config(SilenceBehavior::Publish).silence;
+        let elapsed = WINDOW as f32 / SAMPLE_RATE as f32;
+        let mut detector = SilenceDetector::default();
+
+        assert_eq!(
+            detector.update(&tone(), elapsed, &config),
+            SilenceState::Sound
+        );
+        for _ in 0..3 {
+            let state = detector.update(&silence(), elapsed, &config);
+            assert_eq!(state, SilenceState::Quiet);
+        }
+        let state = detector.update(&silence(), elapsed, &config);
+        assert_eq!(state, SilenceState::Silent);
+
+        // sound starts the hold over
+        assert_eq!(
+            detector.update(&tone(), elapsed, &config),
+            SilenceState::Sound
+        );
+        let state = detector.update(&silence(), elapsed, &config);
+        assert_eq!(state, SilenceState::Quiet);
+    }
+
+    #[test]
+    fn publish_keeps_publishing() {
+        let mut processor = processor(SilenceBehavior::Publish);
+        assert!(processor.process_samples(&tone()));
+        for _ in 0..8 {
+            assert!(processor.process_samples(&silence()));
+        }
+        assert_eq!(processor.silence(), SilenceState::Silent);
+    }
+
+    #[test]
+    fn stop_publishes_nothing_until_sound_returns() {
+        let mut processor = processor(SilenceBehavior::Stop);
+        assert!(processor.process_samples(&tone()));
+
+        // still quiet rather than silent for the hold time
+        for _ in 0..3 {
+            assert!(processor.process_samples(&silence()));
+        }
+        for _ in 0..4 {
+            assert!(!processor.process_samples(&silence()));
+        }
+
+        assert!(processor.process_samples(&tone()));
+        assert_eq!(processor.silence(), SilenceState::Sound);
+        assert!(loudest(&processor) > 0.0);
+    }
+
+    #[test]
+    fn decay_lets_the_bars_fall_then_stops() {
+        // silent straight away, so the bars are still up when it starts
+        let config = Config {
+            silence: Silence {
+                hold: 0.0,
+                ..config(SilenceBehavior::Decay).silence
+            },
+            ..config(SilenceBehavior::Decay)
+        };
+        let mut processor = Processor::new(SAMPLE_RATE, 1, WINDOW, config).unwrap();
+        processor.set_bands(16);
+
+        assert!(processor.process_samples(&tone()));
+        assert!(loudest(&processor) > 0.0);
+
+        // the bars fall, and then there's nothing left to publish
+        assert!(processor.process_samples(&silence()));
+        assert_eq!(processor.silence(), SilenceState::Silent);
+        assert_eq!(loudest(&processor), 0.0);
+        for _ in 0..4 {
+            assert!(!processor.process_samples(&silence()));
+        }
+
+        assert!(processor.process_samples(&tone()));
+        assert!(loudest(&processor) > 0.0);
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use super::{Band, Channel, Tilt};
+
+/// The tilt's gain for every band, kept until the tilt or the bands change
+#[derive(Default)]
+pub struct TiltTable {
+    layout: Option<(Tilt, Arc<[Band]>)>,
+    gains: Box<[f32]>,
+}
+
+impl TiltTable {
+    pub fn update(&mut self, tilt: Tilt, bands: &Arc<[Band]>) {
+        let unchanged = self
+            .layout
+            .as_ref()
+            .is_some_and(|(old, old_bands)| *old == tilt && Arc::ptr_eq(old_bands, bands));
+        if unchanged {
+            return;
+        }
+
+        // a flat tilt has nothing to multiply by
+        self.gains = match tilt.slope == 0.0 {
+            true => Box::default(),
+            false => bands
+                .iter()
+                .map(|band| {
+                    let octaves = (band.center.max(f32::MIN_POSITIVE) / tilt.pivot).log2();
+                    10.0_f32.powf(tilt.slope * octaves / 20.0)
+                })
+                .collect(),
+        };
+        self.layout = Some((tilt, bands.clone()));
+    }
+
+    #[profiling::function]
+    pub fn apply(&self, channel: &mut Channel) {
+        for (magnitude, gain) in channel.band_magnitudes.iter_mut().zip(&self.gains) {
+            *magnitude *= gain;
+        }
+    }
+}
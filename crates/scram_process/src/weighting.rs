@@ -0,0 +1,226 @@
+use std::f64::consts::{PI, TAU};
+
+use super::Weighting;
+
+/// IEC 61672 A-weighting, close to how loud quiet sounds seem
+fn a_weighting(hz: f64) -> f64 {
+    let f2 = hz * hz;
+    let r = 12194.0_f64.powi(2) * f2 * f2
+        / ((f2 + 20.6_f64.powi(2))
+            * ((f2 + 107.7_f64.powi(2)) * (f2 + 737.9_f64.powi(2))).sqrt()
+            * (f2 + 12194.0_f64.powi(2)));
+    // +2.00 dB, so 1 kHz passes unchanged
+    r * 1.258_925_4
+}
+
+/// IEC 61672 C-weighting, close to how loud very loud sounds seem
+fn c_weighting(hz: f64) -> f64 {
+    let f2 = hz * hz;
+    let r = 12194.0_f64.powi(2) * f2 / ((f2 + 20.6_f64.powi(2)) * (f2 + 12194.0_f64.powi(2)));
+    // +0.06 dB, so 1 kHz passes unchanged
+    r * 1.006_932_4
+}
+
+/// The magnitude of a biquad's response at `omega` radians per sample
+fn biquad(b: [f64; 3], a: [f64; 3], omega: f64) -> f64 {
+    let response = |c: [f64; 3]| {
+        let re = c[0] + c[1] * omega.cos() + c[2] * (2.0 * omega).cos();
+        let im = c[1] * omega.sin() + c[2] * (2.0 * omega).sin();
+        re.hypot(im)
+    };
+    response(b) / response(a)
+}
+
+/// ITU-R BS.1770 K-weighting, the shelf and high-pass that loudness meters use. The filters are
+/// derived for `sample_rate` the way libebur128 does, which gives the standard's coefficients
+/// at 48 kHz
+fn k_weighting(hz: f64, sample_rate: f64) -> f64 {
+    let omega = TAU * hz / sample_rate;
+
+    let shelf = {
+        let (gain, q, center) = (
+            3.999_843_853_973_347,
+            0.707_175_236_955_419_6,
+            1_681.974_450_955_533,
+        );
+        let k = (PI * center / sample_rate).tan();
+        let vh = 10.0_f64.powf(gain / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let b = [
+            vh + vb * k / q + k * k,
+            2.0 * (k * k - vh),
+            vh - vb * k / q + k * k,
+        ];
+        let a = [
+            1.0 + k / q + k * k,
+            2.0 * (k * k - 1.0),
+            1.0 - k / q + k * k,
+        ];
+        biquad(b, a, omega)
+    };
+
+    let high_pass = {
+        let (q, center) = (0.500_327_037_325_395_3, 38.135_470_876_139_82);
+        let k = (PI * center / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let b = [1.0, -2.0, 1.0];
+        let a = [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0];
+        biquad(b, a, omega)
+    };
+
+    shelf * high_pass
+}
+
+/// ITU-R 468 weighting, for how annoying noise is rather than how loud
+fn itu_468(hz: f64) -> f64 {
+    let h1 = -4.737_338_981_378_384e-24 * hz.powi(6) + 2.043_828_333_606_125e-15 * hz.powi(4)
+        - 1.363_894_795_463_638e-7 * hz.powi(2)
+        + 1.0;
+    let h2 = 1.306_612_257_412_824e-19 * hz.powi(5) - 2.118_150_887_518_656e-11 * hz.powi(3)
+        + 5.559_488_023_498_642e-4 * hz;
+    let r = 1.246_332_637_532_143e-4 * hz / h1.hypot(h2);
+    // +18.2 dB, so 1 kHz passes unchanged
+    r * 8.128_305_2
+}
+
+/// The linear gain of `weighting` at `hz`
+pub fn gain(weighting: Weighting, hz: f32, sample_rate: u32) -> f32 {
+    let hz = hz as f64;
+    let gain = match weighting {
+        Weighting::None => 1.0,
+        Weighting::A => a_weighting(hz),
+        Weighting::C => c_weighting(hz),
+        Weighting::K => k_weighting(hz, sample_rate as f64),
+        Weighting::Itu468 => itu_468(hz),
+    };
+    gain as f32
+}
+
+/// The gain of every fft bin, kept until the weighting, bins or sample rate change
+#[derive(Default)]
+pub struct WeightingTable {
+    layout: Option<(Weighting, usize, u32)>,
+    gains: Box<[f32]>,
+}
+
+impl WeightingTable {
+    pub fn update(&mut self, weighting: Weighting, bins: usize, sample_rate: u32) {
+        let layout = (weighting, bins, sample_rate);
+        if self.layout == Some(layout) {
+            return;
+        }
+
+        // without a weighting there's nothing to multiply by
+        let bins = match weighting {
+            Weighting::None => 0,
+            _ => bins,
+        };
+        let hz_per = (sample_rate as f32 / 2.0) / (bins.max(2) - 1) as f32;
+        self.gains = (0..bins)
+            .map(|bin| gain(weighting, bin as f32 * hz_per, sample_rate))
+            .collect();
+        self.layout = Some(layout);
+    }
+
+    #[profiling::function]
+    pub fn apply(&self, magnitudes: &mut [f32]) {
+        for (magnitude, gain) in magnitudes.iter_mut().zip(&self.gains) {
+            *magnitude *= gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The gain of `weighting` at `hz`, in dB, at 48 kHz
+    fn db(weighting: Weighting, hz: f32) -> f32 {
+        20.0 * gain(weighting, hz, 48_000).log10()
+    }
+
+    fn assert_response(weighting: Weighting, expected: &[(f32, f32)], tolerance: f32) {
+        for &(hz, expected) in expected {
+            let db = db(weighting, hz);
+            assert!(
+                (db - expected).abs() <= tolerance,
+                "{weighting:?} is {db} dB at {hz} Hz, not {expected}"
+            );
+        }
+    }
+
+    // the standards' tables are for the exact base-10 frequencies behind the nominal ones,
+    // like 31.62 Hz for 31.5 Hz
+    const HZ_31_5: f32 = 31.622_776;
+    const HZ_4K: f32 = 3_981.071_8;
+    const HZ_16K: f32 = 15_848.932;
+
+    #[test]
+    fn a_weighting_follows_iec_61672() {
+        let expected = [
+            (HZ_31_5, -39.4),
+            (100.0, -19.1),
+            (1_000.0, 0.0),
+            (HZ_4K, 1.0),
+            (10_000.0, -2.5),
+            (HZ_16K, -6.6),
+        ];
+        assert_response(Weighting::A, &expected, 0.05);
+    }
+
+    #[test]
+    fn c_weighting_follows_iec_61672() {
+        let expected = [
+            (HZ_31_5, -3.0),
+            (100.0, -0.3),
+            (1_000.0, 0.0),
+            (HZ_4K, -0.8),
+            (10_000.0, -4.4),
+            (HZ_16K, -8.5),
+        ];
+        assert_response(Weighting::C, &expected, 0.05);
+    }
+
+    #[test]
+    fn k_weighting_follows_bs_1770() {
+        let expected = [(1_000.0, 0.70), (1_500.0, 2.04), (2_000.0, 3.07)];
+        assert_response(Weighting::K, &expected, 0.01);
+
+        // the coefficients the standard gives for 48 kHz
+        let shelf = (
+            [
+                1.535_124_859_586_97,
+                -2.691_696_189_406_38,
+                1.198_392_810_852_85,
+            ],
+            [1.0, -1.690_659_293_182_41, 0.732_480_774_215_85],
+        );
+        let high_pass = (
+            [1.0, -2.0, 1.0],
+            [1.0, -1.990_047_454_833_98, 0.990_072_250_366_21],
+        );
+        for hz in [
+            20.0, 50.0, 100.0, 500.0, 1_000.0, 5_000.0, 10_000.0, 20_000.0,
+        ] {
+            let omega = TAU * hz / 48_000.0;
+            let standard =
+                biquad(shelf.0, shelf.1, omega) * biquad(high_pass.0, high_pass.1, omega);
+            let db = 20.0 * (k_weighting(hz, 48_000.0) / standard).log10();
+            assert!(db.abs() < 0.001, "{db} dB off at {hz} Hz");
+        }
+    }
+
+    #[test]
+    fn itu_468_follows_its_table() {
+        let expected = [
+            (31.5, -29.9),
+            (100.0, -19.8),
+            (1_000.0, 0.0),
+            (2_000.0, 5.6),
+            (6_300.0, 12.2),
+            (10_000.0, 8.1),
+            (20_000.0, -22.2),
+        ];
+        assert_response(Weighting::Itu468, &expected, 0.1);
+    }
+}
@@ -0,0 +1,233 @@
+use std::f64::consts::TAU;
+
+use super::Window;
+
+const HANN: [f64; 2] = [0.5, 0.5];
+const HAMMING: [f64; 2] = [0.54, 0.46];
+const BLACKMAN: [f64; 3] = [0.42, 0.5, 0.08];
+const BLACKMAN_HARRIS: [f64; 4] = [0.35875, 0.48829, 0.14128, 0.01168];
+/// Nuttall's four-term window with a continuous first derivative
+const NUTTALL: [f64; 4] = [0.3635819, 0.4891775, 0.1365995, 0.0106411];
+/// The flat top of ISO 18431-2, which reads a sine's amplitude right wherever it falls in a bin
+const FLAT_TOP: [f64; 5] = [
+    0.21557895,
+    0.41663158,
+    0.277263158,
+    0.083578947,
+    0.006947368,
+];
+
+/// `a0 - a1 cos(2πx) + a2 cos(4πx) - ..`, for `x` from 0 to 1 across the window
+fn cosine_sum(coefficients: &[f64], x: f64) -> f64 {
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(k, a)| {
+            let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+            sign * a * (TAU * k as f64 * x).cos()
+        })
+        .sum()
+}
+
+/// The zeroth-order modified Bessel function of the first kind, from its power series
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term) = (1.0, 1.0);
+    for k in 1..64 {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// The symmetric window of `size` samples, so both ends carry the same weight
+pub fn coefficients(window: &Window, size: usize) -> Vec<f32> {
+    if size <= 1 {
+        return vec![1.0; size];
+    }
+
+    let last = (size - 1) as f64;
+    (0..size)
+        .map(|n| {
+            // 0 at the first sample, 1 at the last
+            let x = n as f64 / last;
+            let w = match *window {
+                Window::None => 1.0,
+                Window::Hann => cosine_sum(&HANN, x),
+                Window::Hamming => cosine_sum(&HAMMING, x),
+                Window::Blackman => cosine_sum(&BLACKMAN, x),
+                Window::BlackmanHarris => cosine_sum(&BLACKMAN_HARRIS, x),
+                Window::Nuttall => cosine_sum(&NUTTALL, x),
+                Window::FlatTop => cosine_sum(&FLAT_TOP, x),
+                Window::Kaiser { beta } => {
+                    let beta = beta as f64;
+                    let r = 2.0 * x - 1.0;
+                    bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(beta)
+                }
+                Window::Tukey { alpha } => {
+                    let alpha = (alpha as f64).clamp(0.0, 1.0);
+                    // how far into the taper at the nearest end, which is flat past 1
+                    let taper = x.min(1.0 - x) * 2.0 / alpha;
+                    match alpha > 0.0 && taper < 1.0 {
+                        true => 0.5 - 0.5 * (TAU / 2.0 * taper).cos(),
+                        false => 1.0,
+                    }
+                }
+                Window::Gaussian { sigma } => {
+                    let r = (2.0 * x - 1.0) / sigma as f64;
+                    (-0.5 * r * r).exp()
+                }
+            };
+            w as f32
+        })
+        .collect()
+}
+
+/// A window's coefficients for one size, kept until the window or size changes
+///
+/// They're scaled by the window's coherent gain, so a sine reads the same level whichever
+/// window is used
+#[derive(Default)]
+pub struct WindowTable {
+    window: Option<Window>,
+    coefficients: Box<[f32]>,
+}
+
+impl WindowTable {
+    pub fn update(&mut self, window: &Window, size: usize) {
+        if self.window.as_ref() == Some(window) && self.coefficients.len() == size {
+            return;
+        }
+
+        let mut coefficients = coefficients(window, size);
+        let gain = coefficients.iter().sum::<f32>() / size.max(1) as f32;
+        if gain > 0.0 {
+            coefficients.iter_mut().for_each(|w| *w /= gain);
+        }
+
+        self.window = Some(*window);
+        self.coefficients = coefficients.into();
+    }
+
+    pub fn coefficients(&self) -> &[f32] {
+        &self.coefficients
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn assert_coefficients(window: Window, expected: &[f32]) {
+        let actual = coefficients(&window, expected.len());
+        for (i, (a, b)) in actual.iter().zip(expected).enumerate() {
+            assert!(
+                (a - b).abs() <= 1e-6,
+                "coefficient {i} of {window:?}: expected {b}, got {a}"
+            );
+        }
+    }
+
+    // references are numpy's hanning, hamming, blackman and kaiser(12, 14)
+
+    #[test]
+    fn hann() {
+        assert_coefficients(
+            Window::Hann,
+            &[
+                0.0, 0.0793732, 0.2922925, 0.5711574, 0.8274304, 0.9797465, 0.9797465, 0.8274304,
+                0.5711574, 0.2922925, 0.0793732, 0.0,
+            ],
+        );
+    }
+
+    #[test]
+    fn hamming() {
+        assert_coefficients(
+            Window::Hamming,
+            &[
+                0.08, 0.1530234, 0.3489091, 0.6054648, 0.8412359, 0.9813668, 0.9813668, 0.8412359,
+                0.6054648, 0.3489091, 0.1530234, 0.08,
+            ],
+        );
+    }
+
+    #[test]
+    fn blackman() {
+        assert_coefficients(
+            Window::Blackman,
+            &[
+                0.0, 0.0326064, 0.1599036, 0.414398, 0.7360452, 0.9670468, 0.9670468, 0.7360452,
+                0.414398, 0.1599036, 0.0326064, 0.0,
+            ],
+        );
+    }
+
+    #[test]
+    fn kaiser() {
+        assert_coefficients(
+            Window::Kaiser { beta: 14.0 },
+            &[
+                0.0000077, 0.0034601, 0.04652, 0.2297371, 0.5998853, 0.9456749, 0.9456749,
+                0.5998853, 0.2297371, 0.04652, 0.0034601, 0.0000077,
+            ],
+        );
+    }
+
+    #[test]
+    fn cosine_sums_meet_their_ends_and_peak() {
+        // an odd size has a sample right in the middle
+        for (window, end, peak) in [
+            (Window::BlackmanHarris, 6.0e-5, 1.0),
+            (Window::Nuttall, 3.628e-4, 1.0),
+            (Window::FlatTop, -4.21e-4, 1.0),
+        ] {
+            let w = coefficients(&window, 9);
+            assert!((w[0] - end).abs() <= 1e-6, "{window:?} starts at {}", w[0]);
+            assert!((w[8] - end).abs() <= 1e-6, "{window:?} ends at {}", w[8]);
+            assert!((w[4] - peak).abs() <= 1e-6, "{window:?} peaks at {}", w[4]);
+        }
+    }
+
+    #[test]
+    fn tukey_between_rectangle_and_hann() {
+        assert_coefficients(Window::Tukey { alpha: 0.0 }, &[1.0; 12]);
+        assert_coefficients(
+            Window::Tukey { alpha: 1.0 },
+            &coefficients(&Window::Hann, 12),
+        );
+        assert_coefficients(
+            Window::Tukey { alpha: 0.5 },
+            &[0.0, 0.75, 1.0, 1.0, 1.0, 0.75, 0.0],
+        );
+    }
+
+    #[test]
+    fn gaussian() {
+        let sigma = 0.4_f32;
+        let w = coefficients(&Window::Gaussian { sigma }, 11);
+        let end = (-0.5 / (sigma * sigma)).exp();
+        assert!((w[0] - end).abs() <= 1e-6);
+        assert!((w[10] - end).abs() <= 1e-6);
+        assert_eq!(w[5], 1.0);
+    }
+
+    #[test]
+    fn compensates_for_coherent_gain() {
+        for window in [
+            Window::None,
+            Window::Hann,
+            Window::Blackman,
+            Window::FlatTop,
+            Window::Kaiser { beta: 8.6 },
+        ] {
+            let mut table = WindowTable::default();
+            table.update(&window, 1024);
+            let mean = table.coefficients().iter().sum::<f32>() / 1024.0;
+            assert!((mean - 1.0).abs() <= 1e-4, "{window:?} averages {mean}");
+        }
+    }
+}
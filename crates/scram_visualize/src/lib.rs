@@ -11,9 +11,12 @@ pub trait Visual {
     fn draw(&mut self, left: &[Frequency], right: &[Frequency], dt: f32, canvas: &mut impl Canvas);
     #[allow(unused)]
     fn resize(&mut self, size: math::Size) {}
+    /// Called with where each band sits in the spectrum whenever that changes
+    #[allow(unused)]
+    fn set_bands(&mut self, bands: &[Band]) {}
 }
 
-pub use scram_process::Frequency;
+pub use scram_process::{Band, Frequency};
 
 pub mod math;
 pub mod surface;